@@ -8,43 +8,240 @@ pub enum BlacklistReason {
     Custom(String),
 }
 
+impl BlacklistReason {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Game(a) => a,
+            Self::Pack(a) => a,
+            Self::Custom(a) => a,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        if name.starts_with("game_") {
+            Self::Game(name[5..].to_owned())
+        } else if name.starts_with("pack_") {
+            Self::Pack(name[5..].to_owned())
+        } else {
+            Self::Custom(name.to_owned())
+        }
+    }
+}
+
+// Image hashes live in the same lists as the SHA-256 ones, just prefixed
+pub const IMAGE_HASH_PREFIX: &str = "dhash:";
+// Anything further than this is not worth reporting even as a "near" match
+pub const MAX_IMAGE_DISTANCE: u32 = 16;
+pub const DEFAULT_IMAGE_DISTANCE: u32 = 6;
+// Game content paths (only models for now), lets us tell a missing asset from one the game ships
+pub const PATH_PREFIX: &str = "path:";
+
 #[derive(Debug)]
 pub struct Blacklist {
-    pub hashes: hashbrown::HashMap<String, Vec<String>>,
+    // SHA-256 -> every list it shows up in
+    pub index: hashbrown::HashMap<[u8; 32], Vec<BlacklistReason>>,
+    pub image_hashes: Vec<(u64, BlacklistReason)>,
+    pub paths: hashbrown::HashMap<String, Vec<BlacklistReason>>,
+    pub skipped: usize, // entries that didn't parse, one typo shouldn't throw away the whole list
 }
 
 impl Blacklist {
     pub fn new(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let lists: hashbrown::HashMap<String, Vec<String>> = serde_json::from_slice(data)?;
+
+        let mut index = hashbrown::HashMap::<[u8; 32], Vec<BlacklistReason>>::new();
+        let mut image_hashes = Vec::new();
+        let mut paths = hashbrown::HashMap::<String, Vec<BlacklistReason>>::new();
+        let mut skipped = 0;
+        for (name, list) in &lists {
+            let reason = BlacklistReason::from_name(name);
+            for hash in list {
+                if hash.starts_with(IMAGE_HASH_PREFIX) {
+                    match u64::from_str_radix(&hash[IMAGE_HASH_PREFIX.len()..], 16) {
+                        Ok(hash) => image_hashes.push((hash, reason.clone())),
+                        Err(_) => skipped += 1,
+                    }
+                } else if hash.starts_with(PATH_PREFIX) {
+                    let reasons = paths.entry(hash[PATH_PREFIX.len()..].to_lowercase()).or_default();
+                    if !reasons.contains(&reason) {
+                        reasons.push(reason.clone());
+                    }
+                } else {
+                    let mut key = [0u8; 32];
+                    if hex::decode_to_slice(hash, &mut key).is_err() {
+                        skipped += 1;
+                        continue;
+                    }
+
+                    let reasons = index.entry(key).or_default();
+                    if !reasons.contains(&reason) {
+                        reasons.push(reason.clone());
+                    }
+                }
+            }
+        }
+
         Ok(Self {
-            hashes: serde_json::from_slice(data)?,
+            index,
+            image_hashes,
+            paths,
+            skipped,
         })
     }
 
-    pub fn check(&self, data: &[u8]) -> Option<BlacklistReason> {
-        let file_hash = {
-            let mut hasher = sha2::Sha256::new();
-            hasher.update(data);
-            hex::encode(hasher.finalize())
-        };
-        // I feel like there's a better way to do this...
-        for (name, hashes) in &self.hashes {
-            for hash in hashes {
-                if file_hash.eq(&hash.to_lowercase()) {
-                    return Some(if name.starts_with("game_") {
-                        BlacklistReason::Game(name[5..].to_owned())
-                    } else if name.starts_with("pack_") {
-                        BlacklistReason::Pack(name[5..].to_owned())
-                    } else {
-                        BlacklistReason::Custom(name.clone())
+    pub fn entry_count(&self) -> usize {
+        self.index.len() + self.image_hashes.len() + self.paths.len()
+    }
+
+    // Closest image within MAX_IMAGE_DISTANCE, re-saved or re-compressed copies won't match the raw SHA
+    pub fn check_image(&self, hash: u64) -> Option<(BlacklistReason, u32)> {
+        self.image_hashes
+            .iter()
+            .map(|(other, reason)| ((hash ^ other).count_ones(), reason))
+            .filter(|(distance, _)| *distance <= MAX_IMAGE_DISTANCE)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(distance, reason)| (reason.clone(), distance))
+    }
+}
+
+// dHash of the image: box filter down to 9x8 luma, then one bit per horizontal gradient.
+// Works on the decoded pixels so it doesn't care about the VTF format or compression used.
+// None if there are fewer pixels than the size says, 0 is a real hash (any flat image).
+pub fn image_hash(rgba: &[u8], width: u32, height: u32) -> Option<u64> {
+    const W: usize = 9;
+    const H: usize = 8;
+
+    let (width, height) = (width.max(1) as usize, height.max(1) as usize);
+    if rgba.len() < width * height * 4 {
+        return None;
+    }
+
+    let mut luma = [[0f32; W]; H];
+    for (cy, row) in luma.iter_mut().enumerate() {
+        let y0 = cy * height / H;
+        let y1 = ((cy + 1) * height / H).max(y0 + 1);
+        for (cx, cell) in row.iter_mut().enumerate() {
+            let x0 = cx * width / W;
+            let x1 = ((cx + 1) * width / W).max(x0 + 1);
+
+            let mut sum = 0f32;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let i = (y * width + x) * 4;
+                    sum += 0.299 * rgba[i] as f32 + 0.587 * rgba[i + 1] as f32 + 0.114 * rgba[i + 2] as f32;
+                }
+            }
+            *cell = sum / ((y1 - y0) * (x1 - x0)) as f32;
+        }
+    }
+
+    let mut hash = 0u64;
+    for row in &luma {
+        for x in 0..W - 1 {
+            hash = (hash << 1) | (row[x] > row[x + 1]) as u64;
+        }
+    }
+    Some(hash)
+}
+
+// What the lists key files by
+pub fn file_hash(data: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(data);
+    key.copy_from_slice(&hasher.finalize());
+    key
+}
+
+pub fn image_hash_string(hash: u64) -> String {
+    format!("{}{:016x}", IMAGE_HASH_PREFIX, hash)
+}
+
+// Which loaded list a match came from, so reviewers know whose word it is
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct BlacklistMatch {
+    pub reason: BlacklistReason,
+    pub source: String,
+}
+
+#[derive(Debug)]
+pub struct LoadedBlacklist {
+    pub name: String,
+    pub enabled: bool,
+    pub priority: i32, // higher goes first
+    pub blacklist: Blacklist,
+}
+
+#[derive(Debug, Default)]
+pub struct BlacklistSet {
+    pub lists: Vec<LoadedBlacklist>,
+    pub load_error: Option<String>, // last file that didn't load, shown in the Blacklists window
+}
+
+impl BlacklistSet {
+    pub fn add(&mut self, name: String, blacklist: Blacklist) {
+        self.lists.push(LoadedBlacklist {
+            name,
+            enabled: true,
+            priority: 0,
+            blacklist,
+        });
+    }
+
+    fn active(&self) -> Vec<&LoadedBlacklist> {
+        let mut active = self.lists.iter().filter(|f| f.enabled).collect::<Vec<_>>();
+        // stable, so load order breaks ties
+        active.sort_by_key(|f| std::cmp::Reverse(f.priority));
+        active
+    }
+
+    pub fn check(&self, key: &[u8; 32]) -> Vec<BlacklistMatch> {
+        let mut ret = Vec::new();
+        for list in self.active() {
+            if let Some(reasons) = list.blacklist.index.get(key) {
+                for reason in reasons {
+                    ret.push(BlacklistMatch {
+                        reason: reason.clone(),
+                        source: list.name.clone(),
+                    });
+                }
+            }
+        }
+        ret
+    }
+
+    // Every list that ships a file at this path, e.g. a prop model the game already has
+    pub fn check_path(&self, path: &str) -> Vec<BlacklistMatch> {
+        let path = path.replace('\\', "/").to_lowercase();
+        let mut ret = Vec::new();
+        for list in self.active() {
+            if let Some(reasons) = list.blacklist.paths.get(&path) {
+                for reason in reasons {
+                    ret.push(BlacklistMatch {
+                        reason: reason.clone(),
+                        source: list.name.clone(),
                     });
                 }
             }
         }
-        None
+        ret
     }
 
-    #[allow(dead_code)]
-    pub fn check_image_data(&self, _data: &[u8]) -> Option<BlacklistReason> {
-        todo!("Deprecate self.check and make this the default?")
+    pub fn check_image(&self, hash: u64) -> Option<(BlacklistMatch, u32)> {
+        let mut best: Option<(BlacklistMatch, u32)> = None;
+        for list in self.active() {
+            if let Some((reason, distance)) = list.blacklist.check_image(hash) {
+                if best.as_ref().map(|f| distance < f.1).unwrap_or(true) {
+                    best = Some((
+                        BlacklistMatch {
+                            reason,
+                            source: list.name.clone(),
+                        },
+                        distance,
+                    ));
+                }
+            }
+        }
+        best
     }
 }
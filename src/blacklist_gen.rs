@@ -0,0 +1,259 @@
+// Builds the JSON `Blacklist::new` eats from a local game install (loose files + _dir.vpk archives)
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use sha2::Digest;
+
+use crate::blacklist::{image_hash_string, PATH_PREFIX};
+use crate::vpk::VPKDir;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub size: u64,
+    pub stamp: u64, // mtime for loose files, CRC for VPK entries
+    pub sha256: String,
+    pub dhash: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Generator {
+    pub name: String, // game_<name> or pack_<name>
+    // relative path (or "<vpk>/<path>") -> what we got last time
+    pub cache: hashbrown::HashMap<String, CacheEntry>,
+
+    pub hashed: usize,
+    pub reused: usize,
+    pub failed: usize,
+}
+
+fn cache_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".cache");
+    output.with_file_name(name)
+}
+
+fn hash_file(path: &str, data: &[u8]) -> (String, Option<String>) {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(data);
+    let sha256 = hex::encode(hasher.finalize());
+
+    // image hashes let us catch re-saved textures too
+    let dhash = if path.ends_with(".vtf")
+        && !crate::vtf::check_header(data).iter().any(|f| f.is_fatal())
+    {
+        match std::panic::catch_unwind(|| crate::vtf::decode_rgba8(data)) {
+            Ok(Ok(image)) => crate::blacklist::image_hash(&image.rgba, image.width as u32, image.height as u32)
+                .map(image_hash_string),
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    (sha256, dhash)
+}
+
+// Path the game would look the file up by, "hl2/models/x.mdl" and "hl2/hl2_misc_dir.vpk/models/x.mdl" are both "models/x.mdl"
+fn content_path(key: &str) -> &str {
+    if let Some(i) = key.find("_dir.vpk/") {
+        return &key[i + "_dir.vpk/".len()..];
+    }
+    if key.starts_with("models/") {
+        return key;
+    }
+    key.find("/models/").map(|i| &key[i + 1..]).unwrap_or(key)
+}
+
+impl Generator {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            cache: hashbrown::HashMap::new(),
+
+            hashed: 0,
+            reused: 0,
+            failed: 0,
+        }
+    }
+
+    // Picks up where the last run for the same output left off
+    pub fn load_cache(&mut self, output: &Path) {
+        let data = if let Ok(v) = std::fs::read(cache_path(output)) {
+            v
+        } else {
+            return;
+        };
+        let json: serde_json::Value = if let Ok(v) = serde_json::from_slice(&data) {
+            v
+        } else {
+            return;
+        };
+        if let Some(map) = json.as_object() {
+            for (path, v) in map {
+                let entry = (|| {
+                    Some(CacheEntry {
+                        size: v.get(0)?.as_u64()?,
+                        stamp: v.get(1)?.as_u64()?,
+                        sha256: v.get(2)?.as_str()?.to_string(),
+                        dhash: v.get(3).and_then(|f| f.as_str()).map(|f| f.to_string()),
+                    })
+                })();
+                if let Some(entry) = entry {
+                    self.cache.insert(path.clone(), entry);
+                }
+            }
+        }
+    }
+
+    fn visit(&mut self, key: String, size: u64, stamp: u64, data: impl FnOnce() -> Option<Vec<u8>>) {
+        if let Some(v) = self.cache.get(&key) {
+            if v.size == size && v.stamp == stamp {
+                self.reused += 1;
+                return;
+            }
+        }
+
+        if let Some(data) = data() {
+            let (sha256, dhash) = hash_file(&key, &data);
+            self.cache.insert(
+                key,
+                CacheEntry {
+                    size,
+                    stamp,
+                    sha256,
+                    dhash,
+                },
+            );
+            self.hashed += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    fn walk(&mut self, root: &Path, dir: &Path, seen: &mut hashbrown::HashSet<String>) {
+        let read_dir = if let Ok(v) = std::fs::read_dir(dir) {
+            v
+        } else {
+            self.failed += 1;
+            return;
+        };
+
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            let meta = if let Ok(v) = entry.metadata() {
+                v
+            } else {
+                self.failed += 1;
+                continue;
+            };
+
+            if meta.is_dir() {
+                self.walk(root, &path, seen);
+                continue;
+            }
+
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/")
+                .to_lowercase();
+
+            if relative.ends_with(".vpk") {
+                if relative.ends_with("_dir.vpk") {
+                    self.walk_vpk(&path, &relative, seen);
+                }
+                // numbered archives are read through their _dir
+                continue;
+            }
+
+            let stamp = meta
+                .modified()
+                .ok()
+                .and_then(|f| f.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|f| f.as_secs())
+                .unwrap_or(0);
+            seen.insert(relative.clone());
+            self.visit(relative, meta.len(), stamp, || std::fs::read(&path).ok());
+        }
+    }
+
+    fn walk_vpk(&mut self, path: &Path, relative: &str, seen: &mut hashbrown::HashSet<String>) {
+        let vpk = match VPKDir::open(path) {
+            Ok(v) => v,
+            Err(v) => {
+                eprintln!("VPK {}: {}", relative, v);
+                self.failed += 1;
+                return;
+            }
+        };
+
+        let mut files = hashbrown::HashMap::new();
+        let mut entries = vpk.entries.iter().collect::<Vec<_>>();
+        // sequential reads, archive by archive
+        entries.sort_by_key(|f| (f.archive_index, f.offset));
+        for entry in entries {
+            let key = format!("{}/{}", relative, entry.path);
+            let size = entry.preload.len() as u64 + entry.length as u64;
+            seen.insert(key.clone());
+            self.visit(key, size, entry.crc as u64, || vpk.read(entry, &mut files).ok());
+        }
+    }
+
+    pub fn scan(&mut self, root: &Path) {
+        let mut seen = hashbrown::HashSet::new();
+        self.walk(root, root, &mut seen);
+        // whatever got removed by a game patch shouldn't linger in the list
+        self.cache.retain(|k, _| seen.contains(k));
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut hashes = self
+            .cache
+            .values()
+            .flat_map(|f| std::iter::once(f.sha256.clone()).chain(f.dhash.clone()))
+            .collect::<Vec<_>>();
+        // only models for now, static props are what needs them
+        hashes.extend(
+            self.cache
+                .keys()
+                .filter(|f| f.ends_with(".mdl"))
+                .map(|f| format!("{}{}", PATH_PREFIX, content_path(f))),
+        );
+        hashes.sort_unstable();
+        hashes.dedup();
+
+        let mut map = hashbrown::HashMap::new();
+        map.insert(self.name.clone(), hashes);
+        Ok(serde_json::to_vec(&map)?)
+    }
+
+    pub fn cache_json(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let map = self
+            .cache
+            .iter()
+            .map(|(k, v)| {
+                (
+                    k.clone(),
+                    serde_json::json!([v.size, v.stamp, v.sha256, v.dhash]),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        Ok(serde_json::to_vec(&serde_json::Value::Object(map))?)
+    }
+
+    pub fn write(&self, output: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::write(output, self.to_json()?)?;
+        std::fs::write(cache_path(output), self.cache_json()?)?;
+        Ok(())
+    }
+}
+
+// Whole thing in one go, regenerating after a game patch only re-hashes what changed
+pub fn generate(root: &Path, name: String, output: &Path) -> Result<Generator, Box<dyn Error>> {
+    let mut generator = Generator::new(name);
+    generator.load_cache(output);
+    generator.scan(root);
+    generator.write(output)?;
+    Ok(generator)
+}
@@ -1,7 +1,10 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
 
-use crate::blacklist::BlacklistReason;
+use crate::blacklist::BlacklistMatch;
+use crate::math;
 
 #[macro_export]
 macro_rules! lump_helper {
@@ -19,6 +22,10 @@ pub enum BSPError {
     InvalidVersion(u32),
 
     InvalidPakFile(u32),
+    InvalidLump(u32, u32),
+    InvalidGameLump(u32, u16), // id, version
+    InvalidLzma(u32),          // offset
+    InvalidLmp(i32),           // lump id
 }
 
 impl fmt::Display for BSPError {
@@ -27,6 +34,15 @@ impl fmt::Display for BSPError {
             Self::InvalidHeader(v) => write!(f, "Invalid header: {:08X}!", v),
             Self::InvalidVersion(v) => write!(f, "Invalid version: {}, must be 19 or 20!", v),
             Self::InvalidPakFile(v) => write!(f, "Invalid PakFile data at: {:X}!", v),
+            Self::InvalidLump(o, s) => write!(f, "Invalid lump at: {:X} ({} bytes)!", o, s),
+            Self::InvalidLzma(v) => write!(f, "Invalid LZMA data at: {:X}!", v),
+            Self::InvalidLmp(v) => write!(f, "Invalid lump file for lump {}!", v),
+            Self::InvalidGameLump(id, v) => write!(
+                f,
+                "Invalid game lump: {} v{}!",
+                String::from_utf8_lossy(&id.to_be_bytes()),
+                v
+            ),
         }
     }
 }
@@ -64,7 +80,8 @@ pub struct PakFile {
     pub compression_algo: PakAlgo,
 
     pub remove: bool,
-    pub blacklisted: Option<BlacklistReason>,
+    pub blacklisted: Vec<BlacklistMatch>,
+    pub sha: Option<[u8; 32]>, // filled on the first blacklist check, rechecks only look it up
 }
 
 impl PakFile {
@@ -98,15 +115,677 @@ impl PakFileLump {
     }
 }
 
+// Lumps that are just an array of fixed size structs
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct StructLump<T> {
+    pub base: BasicLump,
+
+    pub items: Vec<T>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Plane {
+    pub normal: [f32; 3],
+    pub dist: f32,
+    pub kind: i32,
+}
+
+pub type Edge = [u16; 2];
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Face {
+    pub plane: u16,
+    pub side: u8,
+    pub on_node: u8,
+    pub first_edge: i32,
+    pub num_edges: i16,
+    pub texinfo: i16,
+    pub dispinfo: i16,
+    pub fog_volume: i16,
+    pub styles: [u8; 4],
+    pub light_offset: i32,
+    pub area: f32,
+    pub lightmap_mins: [i32; 2],
+    pub lightmap_size: [i32; 2],
+    pub orig_face: i32,
+    pub num_prims: u16,
+    pub first_prim: u16,
+    pub smoothing_groups: u32,
+}
+
+pub const SURF_SKY2D: i32 = 0x2;
+pub const SURF_SKY: i32 = 0x4;
+pub const SURF_TRIGGER: i32 = 0x40;
+pub const SURF_NODRAW: i32 = 0x80;
+pub const SURF_HINT: i32 = 0x100;
+pub const SURF_SKIP: i32 = 0x200;
+pub const SURF_NOLIGHT: i32 = 0x400;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct TexInfo {
+    pub texture_vecs: [[f32; 4]; 2],
+    pub lightmap_vecs: [[f32; 4]; 2],
+    pub flags: i32,
+    pub texdata: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct TexData {
+    pub reflectivity: [f32; 3],
+    pub name_id: i32, // index into the string table
+    pub width: i32,
+    pub height: i32,
+    pub view_width: i32,
+    pub view_height: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct DispInfo {
+    pub start_position: [f32; 3], // which face corner the grid starts at
+    pub disp_vert_start: i32,
+    pub disp_tri_start: i32,
+    pub power: i32, // 2, 3 or 4
+    pub min_tess: i32,
+    pub smoothing_angle: f32,
+    pub contents: i32,
+    pub map_face: u16,
+    pub lightmap_alpha_start: i32,
+    pub lightmap_sample_position_start: i32,
+    // neighbour info skipped, only vbsp cares about it
+    pub allowed_verts: [u32; 10],
+}
+
+impl DispInfo {
+    // vertices per side
+    pub fn size(&self) -> usize {
+        (1 << self.power.max(0).min(4)) + 1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct DispVert {
+    pub vec: [f32; 3], // normalised offset direction
+    pub dist: f32,
+    pub alpha: f32, // 0-255, blend between the two materials of a WorldVertexTransition
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Node {
+    pub plane: i32,
+    pub children: [i32; 2], // negative is -(leaf + 1)
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    pub first_face: u16,
+    pub num_faces: u16,
+    pub area: i16,
+}
+
+// Convex volume, the planes of its sides point out of it
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Brush {
+    pub first_side: i32,
+    pub num_sides: i32,
+    pub contents: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct BrushSide {
+    pub plane: u16,
+    pub texinfo: i16,
+    pub dispinfo: i16,
+    pub bevel: bool, // extra axial planes for collision, not part of the shape
+    pub thin: bool,  // v21 only, was the high byte of bevel before
+}
+
+// Range of a leaf's samples in the ambient lighting lump, one per leaf
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct LeafAmbientIndex {
+    pub sample_count: u16,
+    pub first_sample: u16,
+}
+
+// Light arriving from +X, -X, +Y, -Y, +Z, -Z as RGBE, props without baked lighting are lit by these
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct AmbientSample {
+    pub cube: [[u8; 4]; 6],
+    pub pos: [u8; 3], // fraction of the leaf bounds, 255 is maxs
+}
+
+pub const CONTENTS_SOLID: i32 = 0x1;
+pub const CONTENTS_WINDOW: i32 = 0x2;
+pub const CONTENTS_GRATE: i32 = 0x8;
+pub const CONTENTS_SLIME: i32 = 0x10;
+pub const CONTENTS_WATER: i32 = 0x20;
+pub const CONTENTS_PLAYERCLIP: i32 = 0x10000;
+pub const CONTENTS_MONSTERCLIP: i32 = 0x20000;
+pub const CONTENTS_LADDER: i32 = 0x20000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum EmitType {
+    Surface,
+    Point,
+    Spotlight,
+    Skylight, // light_environment sun
+    QuakeLight,
+    SkyAmbient, // light_environment ambient
+    Unknown(i32),
+}
+
+impl EmitType {
+    fn from_i32(v: i32) -> Self {
+        match v {
+            0 => Self::Surface,
+            1 => Self::Point,
+            2 => Self::Spotlight,
+            3 => Self::Skylight,
+            4 => Self::QuakeLight,
+            5 => Self::SkyAmbient,
+            v => Self::Unknown(v),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Surface => "surface",
+            Self::Point => "point",
+            Self::Spotlight => "spotlight",
+            Self::Skylight => "sky",
+            Self::QuakeLight => "quake",
+            Self::SkyAmbient => "sky ambient",
+            Self::Unknown(_) => "unknown",
+        }
+    }
+}
+
+// What vrad kept of every light for the engine to light models and props with
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct WorldLight {
+    pub origin: [f32; 3],
+    pub intensity: [f32; 3],
+    pub normal: [f32; 3],
+    pub cluster: i32,
+    pub kind: EmitType,
+    pub style: i32,
+    pub stopdot: f32,
+    pub stopdot2: f32,
+    pub exponent: f32,
+    pub radius: f32,
+    pub attenuation: [f32; 3], // constant, linear, quadratic
+    pub flags: i32,
+    pub texinfo: i32,
+    pub owner: i32,
+}
+
+pub const OCCLUDER_FLAGS_INACTIVE: i32 = 0x1;
+
+// One func_occluder, its polygons are a range in OccluderLump.polys
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Occluder {
+    pub flags: i32,
+    pub first_poly: i32,
+    pub poly_count: i32,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    pub area: Option<i32>, // lump version 2 and up
+}
+
+// Vertices are a range in OccluderLump.vertex_indices, which point into the vertexes lump
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct OccluderPoly {
+    pub first_vertex: i32,
+    pub vertex_count: i32,
+    pub plane: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct OccluderLump {
+    pub base: BasicLump,
+
+    pub occluders: Vec<Occluder>,
+    pub polys: Vec<OccluderPoly>,
+    pub vertex_indices: Vec<i32>,
+}
+
+impl OccluderLump {
+    pub fn polys_of(&self, occluder: &Occluder) -> &[OccluderPoly] {
+        if occluder.first_poly < 0 || occluder.poly_count < 0 {
+            return &[];
+        }
+        let start = (occluder.first_poly as usize).min(self.polys.len());
+        let end = (start + occluder.poly_count as usize).min(self.polys.len());
+        &self.polys[start..end]
+    }
+
+    pub fn indices_of(&self, poly: &OccluderPoly) -> &[i32] {
+        if poly.first_vertex < 0 || poly.vertex_count < 0 {
+            return &[];
+        }
+        let start = (poly.first_vertex as usize).min(self.vertex_indices.len());
+        let end = (start + poly.vertex_count as usize).min(self.vertex_indices.len());
+        &self.vertex_indices[start..end]
+    }
+}
+
+// One collision solid of a model, `offset` is into the (decompressed) lump
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct PhysSolid {
+    pub offset: usize,
+    pub size: usize,
+    pub vphy: Option<(u16, u16)>, // version and model type, None for the old headerless format
+}
+
+// Collision of one brush model: binary solids, then a KeyValues text describing them
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct PhysModel {
+    pub model: i32,
+    pub data_size: i32,
+    pub key_size: i32,
+    pub solid_count: i32,
+    pub solids: Vec<PhysSolid>,
+    pub keydata: String,
+    pub problems: Vec<String>, // whatever doesn't add up, the engine trusts all of these sizes
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct PhysCollideLump {
+    pub base: BasicLump,
+
+    pub models: Vec<PhysModel>,
+    pub problems: Vec<String>, // lump level, like a missing terminator
+}
+
+pub const PHYS_VPHY_ID: u32 = u32::from_le_bytes(*b"VPHY");
+
+pub const OVERLAY_MAX_FACES: usize = 64;
+pub const WATER_OVERLAY_MAX_FACES: usize = 256;
+
+// info_overlay (lump 45) or water overlay (lump 50), they only differ in how many faces fit
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Overlay {
+    pub id: i32,
+    pub texinfo: i16,
+    pub face_count: u16, // as stored, can be over the limit in broken maps
+    pub render_order: u8,
+    pub faces: Vec<i32>,
+    pub u: [f32; 2],
+    pub v: [f32; 2],
+    pub uv_points: [[f32; 3]; 4], // x/y in the overlay basis, z packs basis U and a flip flag
+    pub origin: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl Overlay {
+    fn read(data: &[u8], max_faces: usize) -> Self {
+        let packed = read_u16(data, 6);
+        let face_count = packed & 0x3FFF;
+        let faces = (0..(face_count as usize).min(max_faces))
+            .map(|i| read_i32(data, 8 + i * 4))
+            .collect();
+        let rest = 8 + max_faces * 4;
+        Self {
+            id: read_i32(data, 0),
+            texinfo: read_i16(data, 4),
+            face_count,
+            render_order: (packed >> 14) as u8,
+            faces,
+            u: [read_f32(data, rest), read_f32(data, rest + 4)],
+            v: [read_f32(data, rest + 8), read_f32(data, rest + 12)],
+            uv_points: [
+                read_vec3(data, rest + 16),
+                read_vec3(data, rest + 28),
+                read_vec3(data, rest + 40),
+                read_vec3(data, rest + 52),
+            ],
+            origin: read_vec3(data, rest + 64),
+            normal: read_vec3(data, rest + 76),
+        }
+    }
+
+    // The quad in world space, the same way the engine unpacks the basis
+    pub fn corners(&self) -> [[f32; 3]; 4] {
+        let p = &self.uv_points;
+        let basis_u = [p[0][2], p[1][2], p[2][2]];
+        let mut basis_v = math::cross(self.normal, basis_u);
+        if p[3][2] == 1.0 {
+            basis_v = math::scale(basis_v, -1.0);
+        }
+        let mut ret = [[0.0; 3]; 4];
+        for (i, point) in p.iter().enumerate() {
+            ret[i] = math::add(
+                self.origin,
+                math::add(math::scale(basis_u, point[0]), math::scale(basis_v, point[1])),
+            );
+        }
+        ret
+    }
+
+    // Face indices the engine would read out of bounds with, plus whether the count itself is too big
+    pub fn invalid_faces(&self, face_total: usize, max_faces: usize) -> (Vec<i32>, bool) {
+        let invalid = self
+            .faces
+            .iter()
+            .filter(|f| **f < 0 || **f as usize >= face_total)
+            .copied()
+            .collect();
+        (invalid, self.face_count as usize > max_faces)
+    }
+}
+
+// env_cubemap after vbsp, `size` 0 is the default resolution
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct CubemapSample {
+    pub origin: [i32; 3],
+    pub size: i32,
+}
+
+impl CubemapSample {
+    // Face resolution buildcubemaps renders it at
+    pub fn resolution(&self) -> u32 {
+        match self.size {
+            1..=13 => 1 << (self.size - 1),
+            _ => 32,
+        }
+    }
+
+    // `c<x>_<y>_<z>`, the name of the packed VTF without the extension
+    pub fn file_stem(&self) -> String {
+        format!("c{}_{}_{}", self.origin[0], self.origin[1], self.origin[2])
+    }
+}
+
+// Brush model, 0 is the world and the rest are what `"model" "*N"` on brush entities points at
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Model {
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    pub origin: [f32; 3],
+    pub head_node: i32,
+    pub first_face: i32,
+    pub num_faces: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Leaf {
+    pub contents: i32,
+    pub cluster: i16, // -1 for solid leafs
+    pub area_flags: i16, // area:9 flags:7
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    pub first_leaf_face: u16,
+    pub num_leaf_faces: u16,
+    pub first_leaf_brush: u16,
+    pub num_leaf_brushes: u16,
+    pub leaf_water_data: i16,
+    // v0 has the ambient light cube in here, lumps 55/56 took that over in v1
+}
+
+// PVS and PAS per cluster, run-length compressed
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct VisLump {
+    pub base: BasicLump,
+
+    pub num_clusters: usize,
+    pub offsets: Vec<[i32; 2]>, // PVS, PAS into `data`
+    pub data: Vec<u8>,          // the whole lump, offsets count from its start
+}
+
+impl VisLump {
+    // Runs of zero bytes are stored as 0 followed by the count
+    fn decompress(&self, offset: i32) -> Option<Vec<u8>> {
+        let bytes = (self.num_clusters + 7) / 8;
+        if offset < 0 {
+            return None;
+        }
+        let mut pos = offset as usize;
+        let mut ret = Vec::with_capacity(bytes);
+        while ret.len() < bytes {
+            let v = *self.data.get(pos)?;
+            pos += 1;
+            if v == 0 {
+                let count = *self.data.get(pos)? as usize;
+                pos += 1;
+                ret.resize(ret.len() + count, 0);
+            } else {
+                ret.push(v);
+            }
+        }
+        ret.truncate(bytes);
+        Some(ret)
+    }
+
+    // Which clusters can be seen from `cluster`, bit per cluster
+    pub fn pvs(&self, cluster: usize) -> Option<Vec<u8>> {
+        self.decompress(self.offsets.get(cluster)?[0])
+    }
+}
+
+// Tessellated displacement vertex
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct DispPoint {
+    pub pos: [f32; 3],
+    pub base: [f32; 3], // on the flat base face, lightmaps are projected from here
+    pub alpha: f32,
+}
+
+pub const GAMELUMP_SPRP: u32 = u32::from_be_bytes(*b"sprp");
+pub const GAMELUMP_COMPRESSED: u16 = 0x1;
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct GameLumpEntry {
+    pub id: u32, // fourcc, reads as text big endian
+    pub flags: u16,
+    pub version: u16,
+    pub offset: u32, // from the start of the file, not the lump
+    pub size: u32,
+}
+
+impl GameLumpEntry {
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.id.to_be_bytes()).into_owned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct StaticProp {
+    pub origin: [f32; 3],
+    pub angles: [f32; 3], // pitch yaw roll
+    pub model: u16,       // index into StaticProps::models
+    pub first_leaf: u16,
+    pub leaf_count: u16,
+    pub solid: u8,
+    pub flags: u8,
+    pub skin: i32,
+    pub fade_min: f32,
+    pub fade_max: f32,
+    pub lighting_origin: [f32; 3],
+    pub forced_fade_scale: f32, // v5+
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct StaticProps {
+    pub version: u16,
+    pub models: Vec<String>,
+    pub leaves: Vec<u16>,
+    pub props: Vec<StaticProp>,
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct GameLump {
+    pub base: BasicLump,
+
+    pub entries: Vec<GameLumpEntry>,
+    pub static_props: Option<StaticProps>,
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum BSPLump {
-    Entities(EntityLump), // 0
-    PakFile(PakFileLump), // 40
+    Entities(EntityLump),                // 0
+    Planes(StructLump<Plane>),           // 1
+    TexData(StructLump<TexData>),        // 2
+    Vertexes(StructLump<[f32; 3]>),      // 3
+    Visibility(VisLump),                 // 4
+    Nodes(StructLump<Node>),             // 5
+    TexInfo(StructLump<TexInfo>),        // 6
+    Faces(StructLump<Face>),             // 7, 58 (HDR)
+    Lighting(StructLump<u8>),            // 8, 53 (HDR)
+    Occluders(OccluderLump),             // 9
+    Leafs(StructLump<Leaf>),             // 10
+    Edges(StructLump<Edge>),             // 12
+    SurfEdges(StructLump<i32>),          // 13
+    Models(StructLump<Model>),           // 14
+    WorldLights(StructLump<WorldLight>), // 15, 54 (HDR)
+    LeafFaces(StructLump<u16>),          // 16
+    Brushes(StructLump<Brush>),          // 18
+    BrushSides(StructLump<BrushSide>),   // 19
+    DispInfo(StructLump<DispInfo>),      // 26
+    PhysCollide(PhysCollideLump),        // 29
+    DispVerts(StructLump<DispVert>),     // 33
+    Game(GameLump),                      // 35
+    PakFile(PakFileLump),                // 40
+    Overlays(StructLump<Overlay>),       // 45, 50 (water)
+    Cubemaps(StructLump<CubemapSample>), // 42
+    TexDataStringData(StructLump<u8>),   // 43
+    TexDataStringTable(StructLump<i32>), // 44
+    DispTris(StructLump<u16>),           // 48
+    LeafAmbientIndex(StructLump<LeafAmbientIndex>), // 51 (HDR), 52
+    LeafAmbientLighting(StructLump<AmbientSample>), // 55 (HDR), 56
     // ---
     Unknown(BasicLump),
     None,
 }
 
+impl BSPLump {
+    pub fn base(&self) -> &BasicLump {
+        // None never survives ParsedMap::new
+        const NONE: BasicLump = BasicLump {
+            offset: 0,
+            size: 0,
+            version: 0,
+            four: 0,
+        };
+        match self {
+            Self::Entities(v) => &v.base,
+            Self::Planes(v) => &v.base,
+            Self::TexData(v) => &v.base,
+            Self::Vertexes(v) => &v.base,
+            Self::Visibility(v) => &v.base,
+            Self::Nodes(v) => &v.base,
+            Self::TexInfo(v) => &v.base,
+            Self::Faces(v) => &v.base,
+            Self::Lighting(v) => &v.base,
+            Self::Occluders(v) => &v.base,
+            Self::Leafs(v) => &v.base,
+            Self::Edges(v) => &v.base,
+            Self::SurfEdges(v) => &v.base,
+            Self::Models(v) => &v.base,
+            Self::WorldLights(v) => &v.base,
+            Self::LeafFaces(v) => &v.base,
+            Self::Brushes(v) => &v.base,
+            Self::BrushSides(v) => &v.base,
+            Self::DispInfo(v) => &v.base,
+            Self::PhysCollide(v) => &v.base,
+            Self::DispVerts(v) => &v.base,
+            Self::Game(v) => &v.base,
+            Self::PakFile(v) => &v.base,
+            Self::Overlays(v) => &v.base,
+            Self::Cubemaps(v) => &v.base,
+            Self::TexDataStringData(v) => &v.base,
+            Self::TexDataStringTable(v) => &v.base,
+            Self::DispTris(v) => &v.base,
+            Self::LeafAmbientIndex(v) => &v.base,
+            Self::LeafAmbientLighting(v) => &v.base,
+            Self::Unknown(v) => v,
+            Self::None => &NONE,
+        }
+    }
+
+    pub fn base_mut(&mut self) -> Option<&mut BasicLump> {
+        match self {
+            Self::Entities(v) => Some(&mut v.base),
+            Self::Planes(v) => Some(&mut v.base),
+            Self::TexData(v) => Some(&mut v.base),
+            Self::Vertexes(v) => Some(&mut v.base),
+            Self::Visibility(v) => Some(&mut v.base),
+            Self::Nodes(v) => Some(&mut v.base),
+            Self::TexInfo(v) => Some(&mut v.base),
+            Self::Faces(v) => Some(&mut v.base),
+            Self::Lighting(v) => Some(&mut v.base),
+            Self::Occluders(v) => Some(&mut v.base),
+            Self::Leafs(v) => Some(&mut v.base),
+            Self::Edges(v) => Some(&mut v.base),
+            Self::SurfEdges(v) => Some(&mut v.base),
+            Self::Models(v) => Some(&mut v.base),
+            Self::WorldLights(v) => Some(&mut v.base),
+            Self::LeafFaces(v) => Some(&mut v.base),
+            Self::Brushes(v) => Some(&mut v.base),
+            Self::BrushSides(v) => Some(&mut v.base),
+            Self::DispInfo(v) => Some(&mut v.base),
+            Self::PhysCollide(v) => Some(&mut v.base),
+            Self::DispVerts(v) => Some(&mut v.base),
+            Self::Game(v) => Some(&mut v.base),
+            Self::PakFile(v) => Some(&mut v.base),
+            Self::Overlays(v) => Some(&mut v.base),
+            Self::Cubemaps(v) => Some(&mut v.base),
+            Self::TexDataStringData(v) => Some(&mut v.base),
+            Self::TexDataStringTable(v) => Some(&mut v.base),
+            Self::DispTris(v) => Some(&mut v.base),
+            Self::LeafAmbientIndex(v) => Some(&mut v.base),
+            Self::LeafAmbientLighting(v) => Some(&mut v.base),
+            Self::Unknown(v) => Some(v),
+            Self::None => None,
+        }
+    }
+}
+
+// `<map>_l_<n>.lmp` next to the map, the engine swaps the lump with id `id` for its contents
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct LmpHeader {
+    pub offset: i32, // to the lump data, always the header size in practice
+    pub id: i32,
+    pub version: i32,
+    pub length: i32,
+    pub revision: i32, // map revision it was made for
+}
+
+pub const LMP_HEADER_SIZE: usize = 20;
+
+impl LmpHeader {
+    // The header and the lump data it points to
+    pub fn read(data: &[u8]) -> Result<(Self, &[u8]), BSPError> {
+        if data.len() < LMP_HEADER_SIZE {
+            return Err(BSPError::InvalidLmp(0));
+        }
+        let header = Self {
+            offset: read_i32(data, 0),
+            id: read_i32(data, 4),
+            version: read_i32(data, 8),
+            length: read_i32(data, 12),
+            revision: read_i32(data, 16),
+        };
+        if header.id < 0 || header.id >= 64 {
+            return Err(BSPError::InvalidLmp(header.id));
+        }
+        if header.offset < 0 || header.length < 0 {
+            return Err(BSPError::InvalidLmp(header.id));
+        }
+        let lump = data
+            .get(header.offset as usize..header.offset as usize + header.length as usize)
+            .ok_or(BSPError::InvalidLmp(header.id))?;
+        Ok((header, lump))
+    }
+}
+
+pub fn write_lmp(id: usize, version: u32, revision: u32, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(LMP_HEADER_SIZE + data.len());
+    buf.extend_from_slice(&(LMP_HEADER_SIZE as i32).to_le_bytes());
+    buf.extend_from_slice(&(id as i32).to_le_bytes());
+    buf.extend_from_slice(&version.to_le_bytes());
+    buf.extend_from_slice(&(data.len() as i32).to_le_bytes());
+    buf.extend_from_slice(&revision.to_le_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct ParsedMap {
     // pub name: String,
@@ -116,6 +795,8 @@ pub struct ParsedMap {
     pub buf: Vec<u8>, // move out vector to allow less memory allocation for the web
 
     pub order: Vec<(u8, u32)>,
+    pub compressed: [bool; 64], // LZMA'd on disk, parsed from a decompressed copy
+    pub overrides: Vec<LmpHeader>, // applied .lmp files, their data sits past the end of the map in `buf`
 }
 
 impl ParsedMap {
@@ -134,18 +815,34 @@ impl ParsedMap {
             ver
         };
 
+        let header = |i: usize| unsafe {
+            let lump_start = buf.as_ptr().add(8 + i * 16).cast::<u32>();
+            (
+                lump_start.read_unaligned(),
+                lump_start.add(1).read_unaligned(),
+                lump_start.add(2).read_unaligned(),
+                lump_start.add(3).read_unaligned(),
+            )
+        };
+        // L4D2 flavour of v21 stores {version, offset, size, four}, real offsets are never inside the header
+        let swapped = version == 21
+            && (0..64).any(|i| {
+                let (first, second, _, _) = header(i);
+                first < 0x40C && second != 0
+            });
+
         // hacky way to not allocate memory that much...
         const INIT: BSPLump = BSPLump::None;
         let mut lumps = [INIT; 64];
+        let mut compressed = [false; 64];
         for i in 0..64usize {
-            let (offset, size, version, four) = unsafe {
-                let lump_start = buf.as_ptr().add(8 + i * 16).cast::<u32>();
-                (
-                    *lump_start,
-                    *lump_start.add(1),
-                    *lump_start.add(2),
-                    *lump_start.add(3),
-                )
+            let (offset, size, version, four) = {
+                let (a, b, c, d) = header(i);
+                if swapped {
+                    (b, c, a, d)
+                } else {
+                    (a, b, c, d)
+                }
             };
             let base = BasicLump {
                 offset,
@@ -153,211 +850,25 @@ impl ParsedMap {
                 version,
                 four,
             };
+            compressed[i] = is_lzma(lump_slice(&base, &buf).unwrap_or(&[]));
             lumps[i] = match i {
-                0 => BSPLump::Entities(EntityLump {
-                    base,
-                    string: unsafe {
-                        // LZMA
-                        if &buf[offset as usize..(offset + 4) as usize] == &[0x4C, 0x5A, 0x4D, 0x41]
-                        {
-                            let buf_ptr = buf.as_ptr().add(offset as usize).cast::<u32>();
-                            let decompressed_size = *buf_ptr.add(1);
-                            let compressed_size = *buf_ptr.add(2);
-
-                            // eprintln!("{} | {} | {}", compressed_size, size, four);
-
-                            let inner = &buf[(offset + 12) as usize
-                                ..(offset + 12 + compressed_size + 5) as usize];
-                            let output =
-                                gmod_lzma::decompress_valve(inner, decompressed_size).unwrap();
-
-                            let ret = std::str::from_utf8_unchecked(&output).to_string();
-
-                            // eprintln!("{}", &ret);
-                            eprintln!(
-                                "{} | {} | {} | {} ||| {}",
-                                compressed_size,
-                                decompressed_size,
-                                size,
-                                four,
-                                ret.len()
-                            );
-
-                            #[cfg(debug_assertions)]
-                            {
-                                assert_eq!(decompressed_size, four);
-                                assert_eq!(decompressed_size, ret.len() as u32);
-                            }
-
-                            ret
-                        } else {
-                            let delta = if buf[(offset + size - 1) as usize] == 0 {
-                                1
-                            } else {
-                                0
-                            };
-                            std::str::from_utf8_unchecked(
-                                &buf[offset as usize..(offset + size - delta) as usize],
-                            )
-                            .to_string()
-                        }
-                    },
-                }),
-                40 => {
-                    // Coding at night with constraints be like
-                    let file = &buf[offset as usize..(offset + size) as usize];
-                    let mut position = 0usize;
-
-                    let mut files = Vec::<PakFile>::new();
-
-                    while (position as u32) < size {
-                        let header_pos = position as u32;
-
-                        let header = &file[position..position + 30];
-                        position += 30;
-                        match &header[0..4] {
-                            &[0x50, 0x4B, 3, 4] => {
-                                // // Min version
-                                // if header[4] > 0xA {
-                                //     return Err(Box::new(BSPError::InvalidPakFile(header_pos + 4)));
-                                // }
-
-                                if header[8] != 0 || header[9] != 0 {
-                                    //return Err(Box::new(BSPError::InvalidPakFile(header_pos + 8)));
-                                    if header[8] == 0xE && header[9] == 0 {
-                                        // LZMA
-                                        let compressed_size =
-                                            unsafe { *((&header[18..22]).as_ptr().cast::<u32>()) };
-                                        let data_size =
-                                            unsafe { *((&header[22..26]).as_ptr().cast::<u32>()) };
-                                        let name_size =
-                                            unsafe { *((&header[26..28]).as_ptr().cast::<u16>()) };
-                                        let extra_size =
-                                            unsafe { *((&header[28..30]).as_ptr().cast::<u16>()) };
-
-                                        if extra_size != 0 {
-                                            return Err(Box::new(BSPError::InvalidPakFile(
-                                                header_pos + 28,
-                                            )));
-                                        }
-
-                                        let name = (position as u32, name_size as u32);
-                                        position += name_size as usize;
-                                        position += extra_size as usize;
-
-                                        let compressed_data = (position as u32, compressed_size);
-                                        let real_data = {
-                                            // Explanation:
-                                            // LZMA in ZIP spec: u16(version), u16(props_size)
-
-                                            // let mut r = std::io::Cursor::new(
-                                            //     &file[position + 4
-                                            //         ..position + compressed_size as usize],
-                                            // );
-                                            // let mut decomp =
-                                            //     Vec::<u8>::with_capacity(data_size as usize);
-                                            // if let Err(v) = lzma_rs::lzma_decompress_with_options(&mut r, &mut decomp, &lzma_rs::decompress::Options {
-                                            //     unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(data_size as u64)),
-                                            //     ..Default::default()
-                                            // }) {
-                                            //     eprintln!("{:#?}", v);
-                                            //     return Err(Box::new(BSPError::InvalidPakFile(position as u32)));
-                                            // } else {
-                                            //     Some(decomp)
-                                            // }
-
-                                            let data = &file
-                                                [position + 4..position + compressed_size as usize];
-                                            if let Ok(decomp) =
-                                                gmod_lzma::decompress_valve(data, data_size)
-                                            {
-                                                Some(decomp)
-                                            } else {
-                                                return Err(Box::new(BSPError::InvalidPakFile(
-                                                    position as u32,
-                                                )));
-                                            }
-                                        };
-                                        position += compressed_size as usize;
-
-                                        files.push(PakFile {
-                                            name,
-                                            data: compressed_data,
-                                            remove: false,
-
-                                            real_data,
-                                            compression_algo: PakAlgo::LZMA(
-                                                compressed_size,
-                                                data_size,
-                                            ),
-
-                                            blacklisted: None,
-                                        })
-                                    } else {
-                                        return Err(Box::new(BSPError::InvalidPakFile(
-                                            header_pos + 8,
-                                        )));
-                                    }
-                                } else {
-                                    // STORE
-
-                                    let data_size =
-                                        unsafe { *((&header[22..26]).as_ptr().cast::<u32>()) };
-                                    let name_size =
-                                        unsafe { *((&header[26..28]).as_ptr().cast::<u16>()) };
-                                    let extra_size =
-                                        unsafe { *((&header[28..30]).as_ptr().cast::<u16>()) };
-
-                                    // let name = unsafe {
-                                    //     std::str::from_utf8_unchecked(
-                                    //         &file[position..position + name_size as usize],
-                                    //     )
-                                    // };
-                                    let name = (position as u32, name_size as u32);
-                                    position += name_size as usize;
-                                    position += extra_size as usize;
-
-                                    // let data = &file[position..position + data_size as usize];
-                                    let data = (position as u32, data_size);
-                                    position += data_size as usize;
-
-                                    files.push(PakFile {
-                                        name,
-                                        data,
-                                        remove: false,
-
-                                        real_data: None,
-                                        compression_algo: PakAlgo::None,
-
-                                        blacklisted: None,
-                                    })
-                                }
-                            }
-                            &[0x50, 0x4B, 1, 2] => {
-                                break; // Central directory aka ending stuff
-                            }
-                            _ => {
-                                return Err(Box::new(BSPError::InvalidPakFile(header_pos)));
-                            }
-                        }
+                0 => parse_entities(base, &buf)?,
+                40 => parse_pakfile(base, &buf)?,
+                _ => match parse_lump(i, base.clone(), &buf) {
+                    Ok(v) => v,
+                    Err(v) => {
+                        // don't refuse to open the map over geometry, it's the pakfile people care about
+                        eprintln!("Lump {}: {}", i, v);
+                        BSPLump::Unknown(base)
                     }
-
-                    BSPLump::PakFile(PakFileLump { base, files })
-                }
-                _ => BSPLump::Unknown(base),
+                },
             };
         }
 
         let iteration = unsafe { *buf.as_ptr().add(0x408).cast::<u32>() };
 
         let mut order = (0..64u8)
-            .map(|i| {
-                let offset = unsafe {
-                    let lump_start = buf.as_ptr().add(8 + i as usize * 16).cast::<u32>();
-                    *lump_start
-                };
-                (i, offset)
-            })
+            .map(|i| (i, lumps[i as usize].base().offset))
             .collect::<Vec<_>>();
         order.sort_by_key(|f| f.1);
 
@@ -368,6 +879,1498 @@ impl ParsedMap {
             buf,
 
             order, // for best compatibility
+            compressed,
+            overrides: Vec::new(),
         })
     }
+
+    // Replaces a lump with the one from a .lmp file. The data is appended to `buf` so every offset
+    // the other lumps hold stays valid, the map can't be saved in place after that.
+    // Nothing changes if the lump doesn't parse.
+    pub fn apply_lmp(&mut self, data: &[u8]) -> Result<LmpHeader, Box<dyn Error>> {
+        let (header, lump) = LmpHeader::read(data)?;
+        let index = header.id as usize;
+        let original_len = self.buf.len();
+        let base = BasicLump {
+            offset: original_len as u32,
+            size: lump.len() as u32,
+            version: header.version as u32,
+            four: 0,
+        };
+        self.buf.extend_from_slice(lump);
+        let parsed = match index {
+            0 => parse_entities(base, &self.buf),
+            40 => parse_pakfile(base, &self.buf),
+            _ => parse_lump(index, base, &self.buf).map_err(|f| f.into()),
+        };
+        match parsed {
+            Ok(v) => {
+                self.lumps[index] = v;
+                self.compressed[index] = is_lzma(lump);
+                self.overrides.push(header.clone());
+                Ok(header)
+            }
+            Err(v) => {
+                self.buf.truncate(original_len);
+                Err(v)
+            }
+        }
+    }
+
+    pub fn planes(&self) -> &[Plane] {
+        match &self.lumps[1] {
+            BSPLump::Planes(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn tex_data(&self) -> &[TexData] {
+        match &self.lumps[2] {
+            BSPLump::TexData(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn tex_info(&self) -> &[TexInfo] {
+        match &self.lumps[6] {
+            BSPLump::TexInfo(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn vertices(&self) -> &[[f32; 3]] {
+        match &self.lumps[3] {
+            BSPLump::Vertexes(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    // LDR faces if the map has them, HDR-only maps exist too
+    pub fn faces(&self) -> &[Face] {
+        match (&self.lumps[7], &self.lumps[58]) {
+            (BSPLump::Faces(v), _) if !v.items.is_empty() => &v.items,
+            (_, BSPLump::Faces(v)) => &v.items,
+            _ => &[],
+        }
+    }
+
+    // Lighting samples and the faces whose light_offset points into them, in the same order as faces().
+    // HDR-only compiles leave the LDR lighting empty, the HDR faces are the ones pointing at lump 53 then.
+    pub fn lighting(&self) -> (&[Face], &[u8]) {
+        match (&self.lumps[8], &self.lumps[53], &self.lumps[58]) {
+            (BSPLump::Lighting(v), _, _) if !v.items.is_empty() => (self.faces(), &v.items),
+            (_, BSPLump::Lighting(v), BSPLump::Faces(faces)) if !v.items.is_empty() => {
+                (&faces.items, &v.items)
+            }
+            _ => (self.faces(), &[]),
+        }
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        match &self.lumps[12] {
+            BSPLump::Edges(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn surf_edges(&self) -> &[i32] {
+        match &self.lumps[13] {
+            BSPLump::SurfEdges(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn visibility(&self) -> Option<&VisLump> {
+        match &self.lumps[4] {
+            BSPLump::Visibility(v) if v.num_clusters > 0 => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        match &self.lumps[5] {
+            BSPLump::Nodes(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn models(&self) -> &[Model] {
+        match &self.lumps[14] {
+            BSPLump::Models(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn leafs(&self) -> &[Leaf] {
+        match &self.lumps[10] {
+            BSPLump::Leafs(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn leaf_faces(&self) -> &[u16] {
+        match &self.lumps[16] {
+            BSPLump::LeafFaces(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn occluders(&self) -> Option<&OccluderLump> {
+        match &self.lumps[9] {
+            BSPLump::Occluders(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn phys_collide(&self) -> Option<&PhysCollideLump> {
+        match &self.lumps[29] {
+            BSPLump::PhysCollide(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    // Per leaf sample ranges and the samples, LDR unless the map only has HDR like lighting().
+    // v19 maps keep a single cube in each leaf instead, both are empty for those.
+    pub fn ambient(&self) -> (&[LeafAmbientIndex], &[AmbientSample]) {
+        let set = |index: usize, lighting: usize| match (&self.lumps[index], &self.lumps[lighting]) {
+            (BSPLump::LeafAmbientIndex(a), BSPLump::LeafAmbientLighting(b)) if !b.items.is_empty() => {
+                Some((&a.items[..], &b.items[..]))
+            }
+            _ => None,
+        };
+        set(52, 56).or_else(|| set(51, 55)).unwrap_or((&[], &[]))
+    }
+
+    pub fn leaf_ambient(&self, leaf: usize) -> &[AmbientSample] {
+        let (indices, samples) = self.ambient();
+        match indices.get(leaf) {
+            Some(index) => {
+                let start = (index.first_sample as usize).min(samples.len());
+                let end = (start + index.sample_count as usize).min(samples.len());
+                &samples[start..end]
+            }
+            None => &[],
+        }
+    }
+
+    pub fn world_lights(&self) -> &[WorldLight] {
+        match &self.lumps[15] {
+            BSPLump::WorldLights(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn world_lights_hdr(&self) -> &[WorldLight] {
+        match &self.lumps[54] {
+            BSPLump::WorldLights(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn overlays(&self) -> &[Overlay] {
+        match &self.lumps[45] {
+            BSPLump::Overlays(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn water_overlays(&self) -> &[Overlay] {
+        match &self.lumps[50] {
+            BSPLump::Overlays(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn cubemaps(&self) -> &[CubemapSample] {
+        match &self.lumps[42] {
+            BSPLump::Cubemaps(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn brushes(&self) -> &[Brush] {
+        match &self.lumps[18] {
+            BSPLump::Brushes(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn brush_sides(&self) -> &[BrushSide] {
+        match &self.lumps[19] {
+            BSPLump::BrushSides(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn sides_of(&self, brush: &Brush) -> &[BrushSide] {
+        let sides = self.brush_sides();
+        if brush.first_side < 0 || brush.num_sides < 0 {
+            return &[];
+        }
+        let start = (brush.first_side as usize).min(sides.len());
+        let end = (start + brush.num_sides as usize).min(sides.len());
+        &sides[start..end]
+    }
+
+    // Walks the BSP tree from the world head node, None if the tree is broken
+    pub fn find_leaf(&self, pos: [f32; 3]) -> Option<usize> {
+        let (nodes, planes) = (self.nodes(), self.planes());
+        let mut node = 0i32;
+        // a valid tree can't be deeper than it has nodes
+        for _ in 0..=nodes.len() {
+            if node < 0 {
+                return Some((-1 - node) as usize);
+            }
+            let v = nodes.get(node as usize)?;
+            let plane = planes.get(v.plane as usize)?;
+            let side = math::dot(plane.normal, pos) - plane.dist;
+            node = v.children[if side >= 0.0 { 0 } else { 1 }];
+        }
+        None
+    }
+
+    // Faces in every leaf visible from `cluster`, indexed like faces()
+    pub fn visible_faces(&self, cluster: usize) -> Option<Vec<bool>> {
+        let pvs = self.visibility()?.pvs(cluster)?;
+        let leaf_faces = self.leaf_faces();
+        let mut ret = vec![false; self.faces().len()];
+        for leaf in self.leafs() {
+            // clusters past the row only show up in broken or hand edited maps
+            let hidden = pvs
+                .get(leaf.cluster as usize / 8)
+                .map_or(true, |b| b & (1 << (leaf.cluster % 8)) == 0);
+            if leaf.cluster < 0 || hidden {
+                continue;
+            }
+            let first = leaf.first_leaf_face as usize;
+            for face in leaf_faces.iter().skip(first).take(leaf.num_leaf_faces as usize) {
+                if let Some(v) = ret.get_mut(*face as usize) {
+                    *v = true;
+                }
+            }
+        }
+        Some(ret)
+    }
+
+    // Average fraction of the map every cluster can see, close to 1 means vvis didn't do its job.
+    // Clusters whose PVS doesn't decompress are left out and counted instead.
+    pub fn vis_coverage(&self) -> Option<(f32, usize)> {
+        let vis = self.visibility()?;
+        let mut visible = 0u64;
+        let mut broken = 0;
+        for cluster in 0..vis.num_clusters {
+            match vis.pvs(cluster) {
+                Some(v) => visible += v.iter().map(|f| f.count_ones() as u64).sum::<u64>(),
+                None => broken += 1,
+            }
+        }
+        let rows = vis.num_clusters - broken;
+        if rows == 0 {
+            return Some((0.0, broken));
+        }
+        Some((visible as f32 / (rows * vis.num_clusters) as f32, broken))
+    }
+
+    pub fn disp_infos(&self) -> &[DispInfo] {
+        match &self.lumps[26] {
+            BSPLump::DispInfo(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    pub fn disp_verts(&self) -> &[DispVert] {
+        match &self.lumps[33] {
+            BSPLump::DispVerts(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    // One tag per triangle, walkable/buildable/etc flags
+    pub fn disp_tris(&self) -> &[u16] {
+        match &self.lumps[48] {
+            BSPLump::DispTris(v) => &v.items,
+            _ => &[],
+        }
+    }
+
+    // size*size grid of the displacement, rows go from the start corner along the first edge.
+    // None if the base face isn't a quad or the vertices point outside of the lump.
+    pub fn disp_vertices(&self, disp: &DispInfo) -> Option<Vec<DispPoint>> {
+        let face = self.faces().get(disp.map_face as usize)?;
+        let corners = self.face_vertices(face)?;
+        if corners.len() != 4 {
+            return None;
+        }
+
+        // the face winding can start on any corner
+        let distance = |v: &[f32; 3]| {
+            let d = math::sub(*v, disp.start_position);
+            math::dot(d, d)
+        };
+        let start = (0..4).min_by(|a, b| {
+            distance(&corners[*a])
+                .partial_cmp(&distance(&corners[*b]))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+        let corner = |i: usize| corners[(start + i) % 4];
+
+        let size = disp.size();
+        let first = disp.disp_vert_start.max(0) as usize;
+        let verts = self.disp_verts().get(first..first + size * size)?;
+
+        let step = 1.0 / (size - 1) as f32;
+        let mut ret = Vec::with_capacity(size * size);
+        for row in 0..size {
+            let left = math::lerp(corner(0), corner(1), row as f32 * step);
+            let right = math::lerp(corner(3), corner(2), row as f32 * step);
+            for col in 0..size {
+                let vert = &verts[row * size + col];
+                let base = math::lerp(left, right, col as f32 * step);
+                ret.push(DispPoint {
+                    pos: math::add(base, math::scale(vert.vec, vert.dist)),
+                    base,
+                    alpha: vert.alpha,
+                });
+            }
+        }
+        Some(ret)
+    }
+
+    pub fn static_props(&self) -> Option<&StaticProps> {
+        match &self.lumps[35] {
+            BSPLump::Game(v) => v.static_props.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Material path as the map refers to it (no `materials/` prefix or extension), None if the string table is broken
+    pub fn material_name(&self, texdata: &TexData) -> Option<&str> {
+        let (data, table) = match (&self.lumps[43], &self.lumps[44]) {
+            (BSPLump::TexDataStringData(data), BSPLump::TexDataStringTable(table)) => {
+                (&data.items, &table.items)
+            }
+            _ => return None,
+        };
+
+        let start = *table.get(texdata.name_id as usize)? as usize;
+        let data = data.get(start..)?;
+        let end = data.iter().position(|f| *f == 0).unwrap_or(data.len());
+        std::str::from_utf8(&data[..end]).ok()
+    }
+
+    // Polygon of the face in winding order, None if it points outside of the lumps
+    pub fn face_vertices(&self, face: &Face) -> Option<Vec<[f32; 3]>> {
+        let (vertices, edges, surf_edges) = (self.vertices(), self.edges(), self.surf_edges());
+
+        let first = usize::try_from(face.first_edge).ok()?;
+        let count = face.num_edges.max(0) as usize;
+        surf_edges
+            .get(first..first.checked_add(count)?)?
+            .iter()
+            .map(|surf_edge| {
+                let vertex = if *surf_edge >= 0 {
+                    edges.get(*surf_edge as usize)?[0]
+                } else {
+                    edges.get(surf_edge.unsigned_abs() as usize)?[1]
+                };
+                vertices.get(vertex as usize).copied()
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+pub(crate) fn read_i16(buf: &[u8], pos: usize) -> i16 {
+    i16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+pub(crate) fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+}
+
+pub(crate) fn read_i32(buf: &[u8], pos: usize) -> i32 {
+    read_u32(buf, pos) as i32
+}
+
+pub(crate) fn read_f32(buf: &[u8], pos: usize) -> f32 {
+    f32::from_bits(read_u32(buf, pos))
+}
+
+pub(crate) fn read_vec3(buf: &[u8], pos: usize) -> [f32; 3] {
+    [
+        read_f32(buf, pos),
+        read_f32(buf, pos + 4),
+        read_f32(buf, pos + 8),
+    ]
+}
+
+// Fixed size on-disk structs, `version` is the lump version since some of them grew over time
+pub trait LumpStruct: Sized {
+    fn size(version: u32) -> usize;
+    fn read(data: &[u8], version: u32) -> Self;
+}
+
+impl LumpStruct for Plane {
+    fn size(_: u32) -> usize {
+        20
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            normal: read_vec3(data, 0),
+            dist: read_f32(data, 12),
+            kind: read_i32(data, 16),
+        }
+    }
+}
+
+impl LumpStruct for TexData {
+    fn size(_: u32) -> usize {
+        32
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            reflectivity: read_vec3(data, 0),
+            name_id: read_i32(data, 12),
+            width: read_i32(data, 16),
+            height: read_i32(data, 20),
+            view_width: read_i32(data, 24),
+            view_height: read_i32(data, 28),
+        }
+    }
+}
+
+impl LumpStruct for TexInfo {
+    fn size(_: u32) -> usize {
+        72
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        let vec4 = |pos: usize| {
+            [
+                read_f32(data, pos),
+                read_f32(data, pos + 4),
+                read_f32(data, pos + 8),
+                read_f32(data, pos + 12),
+            ]
+        };
+        Self {
+            texture_vecs: [vec4(0), vec4(16)],
+            lightmap_vecs: [vec4(32), vec4(48)],
+            flags: read_i32(data, 64),
+            texdata: read_i32(data, 68),
+        }
+    }
+}
+
+impl LumpStruct for [f32; 3] {
+    fn size(_: u32) -> usize {
+        12
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        read_vec3(data, 0)
+    }
+}
+
+impl LumpStruct for Edge {
+    fn size(_: u32) -> usize {
+        4
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        [read_u16(data, 0), read_u16(data, 2)]
+    }
+}
+
+impl LumpStruct for u8 {
+    fn size(_: u32) -> usize {
+        1
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        data[0]
+    }
+}
+
+impl LumpStruct for u16 {
+    fn size(_: u32) -> usize {
+        2
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        read_u16(data, 0)
+    }
+}
+
+impl LumpStruct for Node {
+    fn size(_: u32) -> usize {
+        32
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            plane: read_i32(data, 0),
+            children: [read_i32(data, 4), read_i32(data, 8)],
+            mins: [read_i16(data, 12), read_i16(data, 14), read_i16(data, 16)],
+            maxs: [read_i16(data, 18), read_i16(data, 20), read_i16(data, 22)],
+            first_face: read_u16(data, 24),
+            num_faces: read_u16(data, 26),
+            area: read_i16(data, 28),
+        }
+    }
+}
+
+impl LumpStruct for WorldLight {
+    // v1 added a shadow cast offset after the normal
+    fn size(version: u32) -> usize {
+        if version == 0 {
+            88
+        } else {
+            100
+        }
+    }
+
+    fn read(data: &[u8], version: u32) -> Self {
+        let at = if version == 0 { 36 } else { 48 };
+        Self {
+            origin: read_vec3(data, 0),
+            intensity: read_vec3(data, 12),
+            normal: read_vec3(data, 24),
+            cluster: read_i32(data, at),
+            kind: EmitType::from_i32(read_i32(data, at + 4)),
+            style: read_i32(data, at + 8),
+            stopdot: read_f32(data, at + 12),
+            stopdot2: read_f32(data, at + 16),
+            exponent: read_f32(data, at + 20),
+            radius: read_f32(data, at + 24),
+            attenuation: [read_f32(data, at + 28), read_f32(data, at + 32), read_f32(data, at + 36)],
+            flags: read_i32(data, at + 40),
+            texinfo: read_i32(data, at + 44),
+            owner: read_i32(data, at + 48),
+        }
+    }
+}
+
+impl LumpStruct for CubemapSample {
+    fn size(_: u32) -> usize {
+        16
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            origin: [read_i32(data, 0), read_i32(data, 4), read_i32(data, 8)],
+            size: read_i32(data, 12),
+        }
+    }
+}
+
+impl LumpStruct for Brush {
+    fn size(_: u32) -> usize {
+        12
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            first_side: read_i32(data, 0),
+            num_sides: read_i32(data, 4),
+            contents: read_i32(data, 8),
+        }
+    }
+}
+
+impl LumpStruct for BrushSide {
+    fn size(_: u32) -> usize {
+        8
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            plane: read_u16(data, 0),
+            texinfo: read_i16(data, 2),
+            dispinfo: read_i16(data, 4),
+            // v21 split the old i16 bevel into two bytes
+            bevel: data[6] != 0,
+            thin: data[7] != 0,
+        }
+    }
+}
+
+impl LumpStruct for LeafAmbientIndex {
+    fn size(_: u32) -> usize {
+        4
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            sample_count: read_u16(data, 0),
+            first_sample: read_u16(data, 2),
+        }
+    }
+}
+
+impl LumpStruct for AmbientSample {
+    fn size(_: u32) -> usize {
+        28
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        let mut cube = [[0; 4]; 6];
+        for (i, side) in cube.iter_mut().enumerate() {
+            side.copy_from_slice(&data[i * 4..i * 4 + 4]);
+        }
+        Self {
+            cube,
+            pos: [data[24], data[25], data[26]],
+        }
+    }
+}
+
+impl LumpStruct for Model {
+    fn size(_: u32) -> usize {
+        48
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            mins: read_vec3(data, 0),
+            maxs: read_vec3(data, 12),
+            origin: read_vec3(data, 24),
+            head_node: read_i32(data, 36),
+            first_face: read_i32(data, 40),
+            num_faces: read_i32(data, 44),
+        }
+    }
+}
+
+impl LumpStruct for Leaf {
+    // v0 (most v19 maps) still carries the 24 byte light cube
+    fn size(version: u32) -> usize {
+        if version == 0 {
+            56
+        } else {
+            32
+        }
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            contents: read_i32(data, 0),
+            cluster: read_i16(data, 4),
+            area_flags: read_i16(data, 6),
+            mins: [read_i16(data, 8), read_i16(data, 10), read_i16(data, 12)],
+            maxs: [read_i16(data, 14), read_i16(data, 16), read_i16(data, 18)],
+            first_leaf_face: read_u16(data, 20),
+            num_leaf_faces: read_u16(data, 22),
+            first_leaf_brush: read_u16(data, 24),
+            num_leaf_brushes: read_u16(data, 26),
+            leaf_water_data: read_i16(data, 28),
+        }
+    }
+}
+
+impl LumpStruct for DispInfo {
+    fn size(_: u32) -> usize {
+        176
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        let mut allowed_verts = [0u32; 10];
+        for (i, v) in allowed_verts.iter_mut().enumerate() {
+            *v = read_u32(data, 136 + i * 4);
+        }
+        Self {
+            start_position: read_vec3(data, 0),
+            disp_vert_start: read_i32(data, 12),
+            disp_tri_start: read_i32(data, 16),
+            power: read_i32(data, 20),
+            min_tess: read_i32(data, 24),
+            smoothing_angle: read_f32(data, 28),
+            contents: read_i32(data, 32),
+            map_face: read_u16(data, 36),
+            lightmap_alpha_start: read_i32(data, 40),
+            lightmap_sample_position_start: read_i32(data, 44),
+            allowed_verts,
+        }
+    }
+}
+
+impl LumpStruct for DispVert {
+    fn size(_: u32) -> usize {
+        20
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            vec: read_vec3(data, 0),
+            dist: read_f32(data, 12),
+            alpha: read_f32(data, 16),
+        }
+    }
+}
+
+impl LumpStruct for i32 {
+    fn size(_: u32) -> usize {
+        4
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        read_i32(data, 0)
+    }
+}
+
+// dface_t is the same 56 bytes for v19 through v21 (only VTMB's v17 differs, which we don't open)
+impl LumpStruct for Face {
+    fn size(_: u32) -> usize {
+        56
+    }
+
+    fn read(data: &[u8], _: u32) -> Self {
+        Self {
+            plane: read_u16(data, 0),
+            side: data[2],
+            on_node: data[3],
+            first_edge: read_i32(data, 4),
+            num_edges: read_i16(data, 8),
+            texinfo: read_i16(data, 10),
+            dispinfo: read_i16(data, 12),
+            fog_volume: read_i16(data, 14),
+            styles: [data[16], data[17], data[18], data[19]],
+            light_offset: read_i32(data, 20),
+            area: read_f32(data, 24),
+            lightmap_mins: [read_i32(data, 28), read_i32(data, 32)],
+            lightmap_size: [read_i32(data, 36), read_i32(data, 40)],
+            orig_face: read_i32(data, 44),
+            num_prims: read_u16(data, 48),
+            first_prim: read_u16(data, 50),
+            smoothing_groups: read_u32(data, 52),
+        }
+    }
+}
+
+pub(crate) fn lump_slice<'a>(base: &BasicLump, buf: &'a [u8]) -> Result<&'a [u8], BSPError> {
+    buf.get(base.offset as usize..base.offset as usize + base.size as usize)
+        .ok_or(BSPError::InvalidLump(base.offset, base.size))
+}
+
+pub(crate) fn parse_structs<T: LumpStruct>(
+    base: BasicLump,
+    buf: &[u8],
+) -> Result<StructLump<T>, BSPError> {
+    let data = lump_slice(&base, buf)?;
+    let size = T::size(base.version);
+    if data.len() % size != 0 {
+        return Err(BSPError::InvalidLump(base.offset, base.size));
+    }
+
+    let items = data
+        .chunks_exact(size)
+        .map(|f| T::read(f, base.version))
+        .collect();
+    Ok(StructLump { base, items })
+}
+
+pub const LZMA_ID: u32 = u32::from_le_bytes(*b"LZMA");
+
+fn is_lzma(data: &[u8]) -> bool {
+    data.len() >= 17 && read_u32(data, 0) == LZMA_ID
+}
+
+// Valve's header: id, uncompressed size, compressed size, 5 bytes of properties, then the stream.
+// None for data that isn't compressed, `offset` only goes into the error.
+pub(crate) fn decompress_lzma(data: &[u8], offset: u32) -> Result<Option<Vec<u8>>, BSPError> {
+    if !is_lzma(data) {
+        return Ok(None);
+    }
+    let decompressed_size = read_u32(data, 4);
+    let compressed_size = read_u32(data, 8) as usize;
+    let inner = data
+        .get(12..compressed_size.checked_add(12 + 5).ok_or(BSPError::InvalidLzma(offset))?)
+        .ok_or(BSPError::InvalidLzma(offset))?;
+    let output = gmod_lzma::decompress_valve(inner, decompressed_size).map_err(|_| BSPError::InvalidLzma(offset))?;
+    if output.len() != decompressed_size as usize {
+        return Err(BSPError::InvalidLzma(offset));
+    }
+    Ok(Some(output))
+}
+
+// Same header for every lump, so compressed ones are parsed from a copy as if the lump started at 0.
+// The parsed lump keeps the on disk base, saving goes by that.
+fn parse_lump(index: usize, base: BasicLump, buf: &[u8]) -> Result<BSPLump, BSPError> {
+    if let Some(data) = decompress_lzma(lump_slice(&base, buf)?, base.offset)? {
+        let inner = BasicLump {
+            offset: 0,
+            size: data.len() as u32,
+            ..base.clone()
+        };
+        let mut lump = parse_lump_data(index, inner, &data)?;
+        if let Some(v) = lump.base_mut() {
+            *v = base;
+        }
+        return Ok(lump);
+    }
+    parse_lump_data(index, base, buf)
+}
+
+fn parse_lump_data(index: usize, base: BasicLump, buf: &[u8]) -> Result<BSPLump, BSPError> {
+    Ok(match index {
+        1 => BSPLump::Planes(parse_structs(base, buf)?),
+        2 => BSPLump::TexData(parse_structs(base, buf)?),
+        3 => BSPLump::Vertexes(parse_structs(base, buf)?),
+        4 => parse_visibility(base, buf)?,
+        5 => BSPLump::Nodes(parse_structs(base, buf)?),
+        6 => BSPLump::TexInfo(parse_structs(base, buf)?),
+        7 | 58 => BSPLump::Faces(parse_structs(base, buf)?),
+        // RGBE samples, faces point into it by byte offset
+        8 | 53 => BSPLump::Lighting(parse_structs(base, buf)?),
+        9 => parse_occluders(base, buf)?,
+        10 => BSPLump::Leafs(parse_structs(base, buf)?),
+        12 => BSPLump::Edges(parse_structs(base, buf)?),
+        13 => BSPLump::SurfEdges(parse_structs(base, buf)?),
+        14 => BSPLump::Models(parse_structs(base, buf)?),
+        15 | 54 => BSPLump::WorldLights(parse_structs(base, buf)?),
+        16 => BSPLump::LeafFaces(parse_structs(base, buf)?),
+        18 => BSPLump::Brushes(parse_structs(base, buf)?),
+        19 => BSPLump::BrushSides(parse_structs(base, buf)?),
+        26 => BSPLump::DispInfo(parse_structs(base, buf)?),
+        29 => parse_physcollide(base, buf)?,
+        33 => BSPLump::DispVerts(parse_structs(base, buf)?),
+        48 => BSPLump::DispTris(parse_structs(base, buf)?),
+        51 | 52 => BSPLump::LeafAmbientIndex(parse_structs(base, buf)?),
+        55 | 56 => BSPLump::LeafAmbientLighting(parse_structs(base, buf)?),
+        42 => BSPLump::Cubemaps(parse_structs(base, buf)?),
+        45 => parse_overlays(base, buf, OVERLAY_MAX_FACES)?,
+        50 => parse_overlays(base, buf, WATER_OVERLAY_MAX_FACES)?,
+        43 => BSPLump::TexDataStringData(parse_structs(base, buf)?),
+        44 => BSPLump::TexDataStringTable(parse_structs(base, buf)?),
+        35 => parse_game_lump(base, buf)?,
+        _ => BSPLump::Unknown(base),
+    })
+}
+
+// Three counted arrays back to back: occluders, their polygons, the polygons' vertex indices
+fn parse_occluders(base: BasicLump, buf: &[u8]) -> Result<BSPLump, BSPError> {
+    let data = lump_slice(&base, buf)?;
+    // maps without occluders may have nothing at all here
+    if data.is_empty() {
+        return Ok(BSPLump::Occluders(OccluderLump {
+            base,
+            occluders: Vec::new(),
+            polys: Vec::new(),
+            vertex_indices: Vec::new(),
+        }));
+    }
+
+    // v2 added the area at the end
+    let occluder_size = if base.version >= 2 { 40 } else { 36 };
+    let mut pos = 0;
+    let mut array = |size: usize| -> Option<(usize, usize)> {
+        if pos + 4 > data.len() {
+            return None;
+        }
+        let count = read_i32(data, pos);
+        if count < 0 || pos + 4 + count as usize * size > data.len() {
+            return None;
+        }
+        let start = pos + 4;
+        pos = start + count as usize * size;
+        Some((start, count as usize))
+    };
+    let invalid = || BSPError::InvalidLump(base.offset, base.size);
+
+    let (start, count) = array(occluder_size).ok_or_else(invalid)?;
+    let occluders = (0..count)
+        .map(|i| {
+            let at = start + i * occluder_size;
+            Occluder {
+                flags: read_i32(data, at),
+                first_poly: read_i32(data, at + 4),
+                poly_count: read_i32(data, at + 8),
+                mins: read_vec3(data, at + 12),
+                maxs: read_vec3(data, at + 24),
+                area: if occluder_size == 40 { Some(read_i32(data, at + 36)) } else { None },
+            }
+        })
+        .collect();
+
+    let (start, count) = array(12).ok_or_else(invalid)?;
+    let polys = (0..count)
+        .map(|i| {
+            let at = start + i * 12;
+            OccluderPoly {
+                first_vertex: read_i32(data, at),
+                vertex_count: read_i32(data, at + 4),
+                plane: read_i32(data, at + 8),
+            }
+        })
+        .collect();
+
+    let (start, count) = array(4).ok_or_else(invalid)?;
+    let vertex_indices = (0..count).map(|i| read_i32(data, start + i * 4)).collect();
+
+    Ok(BSPLump::Occluders(OccluderLump {
+        base,
+        occluders,
+        polys,
+        vertex_indices,
+    }))
+}
+
+// Never fails past the lump bounds, broken collision is exactly what we want to show
+fn parse_physcollide(base: BasicLump, buf: &[u8]) -> Result<BSPLump, BSPError> {
+    let data = lump_slice(&base, buf)?;
+    let mut models = Vec::new();
+    let mut problems = Vec::new();
+    let mut pos = 0;
+    loop {
+        if pos + 16 > data.len() {
+            // an empty lump is fine, anything else needs the -1 terminator
+            if !data.is_empty() {
+                problems.push(format!("no terminator, lump ends at {}", pos));
+            }
+            break;
+        }
+        let model = read_i32(data, pos);
+        let data_size = read_i32(data, pos + 4);
+        let key_size = read_i32(data, pos + 8);
+        let solid_count = read_i32(data, pos + 12);
+        pos += 16;
+        if model == -1 {
+            if pos != data.len() {
+                problems.push(format!("{} bytes after the terminator", data.len() - pos));
+            }
+            break;
+        }
+
+        let mut model_problems = Vec::new();
+        if data_size < 0 || key_size < 0 || solid_count < 0 {
+            problems.push(format!(
+                "model {}: negative sizes ({} data, {} keys, {} solids), can't read past it",
+                model, data_size, key_size, solid_count
+            ));
+            break;
+        }
+        let end = pos + data_size as usize;
+        if end + key_size as usize > data.len() {
+            problems.push(format!(
+                "model {}: {} + {} bytes doesn't fit in the lump",
+                model, data_size, key_size
+            ));
+            break;
+        }
+
+        let mut solids = Vec::new();
+        let mut at = pos;
+        while at + 4 <= end {
+            let size = read_i32(data, at);
+            if size < 0 || at + 4 + size as usize > end {
+                model_problems.push(format!("solid {} at {} has a bogus size {}", solids.len(), at, size));
+                break;
+            }
+            let solid = &data[at + 4..at + 4 + size as usize];
+            let vphy = if solid.len() >= 8 && read_u32(solid, 0) == PHYS_VPHY_ID {
+                Some((read_u16(solid, 4), read_u16(solid, 6)))
+            } else {
+                None
+            };
+            solids.push(PhysSolid {
+                offset: at + 4,
+                size: size as usize,
+                vphy,
+            });
+            at += 4 + size as usize;
+        }
+        if at != end && model_problems.is_empty() {
+            model_problems.push(format!("{} stray bytes after the solids", end - at));
+        }
+        if solids.len() != solid_count as usize {
+            model_problems.push(format!("header says {} solids, found {}", solid_count, solids.len()));
+        }
+
+        let keydata = String::from_utf8_lossy(&data[end..end + key_size as usize])
+            .trim_end_matches('\0')
+            .to_string();
+        // one "solid" block per solid, vcollide parsing goes by them
+        let solid_blocks = keydata
+            .lines()
+            .filter(|f| f.trim().trim_matches('"') == "solid" || f.trim().starts_with("solid {"))
+            .count();
+        if solid_blocks != solids.len() {
+            model_problems.push(format!("{} solid blocks in the key data for {} solids", solid_blocks, solids.len()));
+        }
+
+        models.push(PhysModel {
+            model,
+            data_size,
+            key_size,
+            solid_count,
+            solids,
+            keydata,
+            problems: model_problems,
+        });
+        pos = end + key_size as usize;
+    }
+
+    Ok(BSPLump::PhysCollide(PhysCollideLump {
+        base,
+        models,
+        problems,
+    }))
+}
+
+// No LumpStruct, the record size depends on which of the two lumps it is
+fn parse_overlays(base: BasicLump, buf: &[u8], max_faces: usize) -> Result<BSPLump, BSPError> {
+    let data = lump_slice(&base, buf)?;
+    let size = 8 + max_faces * 4 + 88;
+    if data.len() % size != 0 {
+        return Err(BSPError::InvalidLump(base.offset, base.size));
+    }
+    let items = data
+        .chunks_exact(size)
+        .map(|f| Overlay::read(f, max_faces))
+        .collect();
+    Ok(BSPLump::Overlays(StructLump { base, items }))
+}
+
+fn parse_visibility(base: BasicLump, buf: &[u8]) -> Result<BSPLump, BSPError> {
+    let data = lump_slice(&base, buf)?;
+    // no vvis leaves the lump empty
+    let num_clusters = if data.len() >= 4 {
+        read_i32(data, 0)
+    } else {
+        0
+    };
+    if num_clusters < 0 || 4 + num_clusters as usize * 8 > data.len().max(4) {
+        return Err(BSPError::InvalidLump(base.offset, base.size));
+    }
+
+    let offsets = (0..num_clusters as usize)
+        .map(|i| [read_i32(data, 4 + i * 8), read_i32(data, 8 + i * 8)])
+        .collect();
+    Ok(BSPLump::Visibility(VisLump {
+        num_clusters: num_clusters as usize,
+        offsets,
+        data: data.to_vec(),
+        base,
+    }))
+}
+
+fn parse_game_lump(base: BasicLump, buf: &[u8]) -> Result<BSPLump, BSPError> {
+    let data = lump_slice(&base, buf)?;
+    let invalid = BSPError::InvalidLump(base.offset, base.size);
+
+    let count = if data.len() >= 4 {
+        read_i32(data, 0)
+    } else {
+        0
+    };
+    if count < 0 || 4 + count as usize * 16 > data.len().max(4) {
+        return Err(invalid);
+    }
+
+    let entries = (0..count as usize)
+        .map(|i| {
+            let pos = 4 + i * 16;
+            GameLumpEntry {
+                id: read_u32(data, pos),
+                flags: read_u16(data, pos + 4),
+                version: read_u16(data, pos + 6),
+                offset: read_u32(data, pos + 8),
+                size: read_u32(data, pos + 12),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // a broken sprp shouldn't hide the rest of the game lumps
+    let static_props = entries
+        .iter()
+        .position(|f| f.id == GAMELUMP_SPRP)
+        .and_then(|i| {
+            let entry = &entries[i];
+            let start = entry.offset as usize;
+            // the size of a compressed entry is the uncompressed one, its data runs up to where the next entry
+            // starts (the directory ends with an empty one for that) or to the end of the file
+            let end = if entry.flags & GAMELUMP_COMPRESSED != 0 {
+                Some(entries.get(i + 1).map_or(buf.len(), |f| f.offset as usize))
+            } else {
+                start.checked_add(entry.size as usize)
+            };
+            let data = end.and_then(|end| buf.get(start..end));
+            let data = data.ok_or(BSPError::InvalidGameLump(entry.id, entry.version)).and_then(|data| {
+                // compressed ones carry the same LZMA header as regular lumps
+                if entry.flags & GAMELUMP_COMPRESSED != 0 {
+                    decompress_lzma(data, entry.offset)?
+                        .map(Cow::Owned)
+                        .ok_or(BSPError::InvalidGameLump(entry.id, entry.version))
+                } else {
+                    Ok(Cow::Borrowed(data))
+                }
+            });
+            match data {
+                Ok(data) => parse_static_props(&data, entry.version)
+                    .map_err(|f| eprintln!("{}", f))
+                    .ok(),
+                Err(v) => {
+                    eprintln!("{}", v);
+                    None
+                }
+            }
+        });
+
+    Ok(BSPLump::Game(GameLump {
+        base,
+        entries,
+        static_props,
+    }))
+}
+
+// v4 (oldest we care about) through v11 (CS:GO), every one just appends fields to the previous.
+// Some games mislabel the version, so the struct size comes from the lump itself.
+fn parse_static_props(data: &[u8], version: u16) -> Result<StaticProps, BSPError> {
+    const MODEL_NAME_LENGTH: usize = 128;
+    const V4_SIZE: usize = 56;
+
+    let invalid = BSPError::InvalidGameLump(GAMELUMP_SPRP, version);
+    if !(4..=11).contains(&version) {
+        return Err(invalid);
+    }
+
+    let count = |pos: usize| -> Option<usize> {
+        let v = read_i32(data.get(pos..pos + 4)?, 0);
+        if v < 0 {
+            None
+        } else {
+            Some(v as usize)
+        }
+    };
+
+    let (models, leaves, rest, prop_count) = (|| {
+        let model_count = count(0)?;
+        let mut pos = 4;
+        let models_end = model_count.checked_mul(MODEL_NAME_LENGTH)?.checked_add(pos)?;
+        let models = data
+            .get(pos..models_end)?
+            .chunks_exact(MODEL_NAME_LENGTH)
+            .map(|f| {
+                let end = f.iter().position(|c| *c == 0).unwrap_or(f.len());
+                String::from_utf8_lossy(&f[..end]).into_owned()
+            })
+            .collect::<Vec<_>>();
+        pos = models_end;
+
+        let leaf_count = count(pos)?;
+        pos += 4;
+        let leaves_end = leaf_count.checked_mul(2)?.checked_add(pos)?;
+        let leaves = data
+            .get(pos..leaves_end)?
+            .chunks_exact(2)
+            .map(|f| read_u16(f, 0))
+            .collect::<Vec<_>>();
+        pos = leaves_end;
+
+        let prop_count = count(pos)?;
+        Some((models, leaves, data.get(pos + 4..)?, prop_count))
+    })()
+    .ok_or(BSPError::InvalidGameLump(GAMELUMP_SPRP, version))?;
+
+    let size = if prop_count > 0 {
+        rest.len() / prop_count
+    } else {
+        V4_SIZE
+    };
+    if size < V4_SIZE || size * prop_count != rest.len() {
+        return Err(invalid);
+    }
+
+    let props = rest
+        .chunks_exact(size)
+        .map(|f| StaticProp {
+            origin: read_vec3(f, 0),
+            angles: read_vec3(f, 12),
+            model: read_u16(f, 24),
+            first_leaf: read_u16(f, 26),
+            leaf_count: read_u16(f, 28),
+            solid: f[30],
+            flags: f[31],
+            skin: read_i32(f, 32),
+            fade_min: read_f32(f, 36),
+            fade_max: read_f32(f, 40),
+            lighting_origin: read_vec3(f, 44),
+            forced_fade_scale: if version >= 5 && size >= 60 {
+                read_f32(f, 56)
+            } else {
+                1.0
+            },
+        })
+        .collect();
+
+    Ok(StaticProps {
+        version,
+        models,
+        leaves,
+        props,
+    })
+}
+
+fn parse_entities(base: BasicLump, buf: &[u8]) -> Result<BSPLump, Box<dyn Error>> {
+    let data = lump_slice(&base, buf)?;
+    let decompressed = decompress_lzma(data, base.offset)?;
+    let data = decompressed.as_deref().unwrap_or(data);
+    // both flavours end with a NUL
+    let data = data.strip_suffix(&[0]).unwrap_or(data);
+    Ok(BSPLump::Entities(EntityLump {
+        string: String::from_utf8_lossy(data).into_owned(),
+        base,
+    }))
+}
+
+fn parse_pakfile(base: BasicLump, buf: &[u8]) -> Result<BSPLump, Box<dyn Error>> {
+    let (offset, size) = (base.offset, base.size);
+    // Coding at night with constraints be like
+    let file = (offset as usize)
+        .checked_add(size as usize)
+        .and_then(|end| buf.get(offset as usize..end))
+        .ok_or(BSPError::InvalidPakFile(offset))?;
+    let mut position = 0usize;
+
+    let mut files = Vec::<PakFile>::new();
+
+    while (position as u32) < size {
+        let header_pos = position as u32;
+
+        let header = file
+            .get(position..position + 30)
+            .ok_or(BSPError::InvalidPakFile(header_pos))?;
+        position += 30;
+        match &header[0..4] {
+            &[0x50, 0x4B, 3, 4] => {
+                // // Min version
+                // if header[4] > 0xA {
+                //     return Err(Box::new(BSPError::InvalidPakFile(header_pos + 4)));
+                // }
+
+                if header[8] != 0 || header[9] != 0 {
+                    //return Err(Box::new(BSPError::InvalidPakFile(header_pos + 8)));
+                    if header[8] == 0xE && header[9] == 0 {
+                        // LZMA
+                        let compressed_size = read_u32(header, 18);
+                        let data_size = read_u32(header, 22);
+                        let name_size = read_u16(header, 26);
+                        let extra_size = read_u16(header, 28);
+
+                        if extra_size != 0 {
+                            return Err(Box::new(BSPError::InvalidPakFile(
+                                header_pos + 28,
+                            )));
+                        }
+
+                        let name = (position as u32, name_size as u32);
+                        position += name_size as usize;
+                        position += extra_size as usize;
+
+                        if position + compressed_size as usize > file.len() {
+                            return Err(Box::new(BSPError::InvalidPakFile(
+                                header_pos + 18,
+                            )));
+                        }
+
+                        let compressed_data = (position as u32, compressed_size);
+                        let real_data = {
+                            // Explanation:
+                            // LZMA in ZIP spec: u16(version), u16(props_size)
+
+                            // let mut r = std::io::Cursor::new(
+                            //     &file[position + 4
+                            //         ..position + compressed_size as usize],
+                            // );
+                            // let mut decomp =
+                            //     Vec::<u8>::with_capacity(data_size as usize);
+                            // if let Err(v) = lzma_rs::lzma_decompress_with_options(&mut r, &mut decomp, &lzma_rs::decompress::Options {
+                            //     unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(data_size as u64)),
+                            //     ..Default::default()
+                            // }) {
+                            //     eprintln!("{:#?}", v);
+                            //     return Err(Box::new(BSPError::InvalidPakFile(position as u32)));
+                            // } else {
+                            //     Some(decomp)
+                            // }
+
+                            let data = file
+                                .get(position + 4..position + compressed_size as usize);
+                            if let Some(Ok(decomp)) =
+                                data.map(|data| gmod_lzma::decompress_valve(data, data_size))
+                            {
+                                Some(decomp)
+                            } else {
+                                return Err(Box::new(BSPError::InvalidPakFile(
+                                    position as u32,
+                                )));
+                            }
+                        };
+                        position += compressed_size as usize;
+
+                        files.push(PakFile {
+                            name,
+                            data: compressed_data,
+                            remove: false,
+
+                            real_data,
+                            compression_algo: PakAlgo::LZMA(
+                                compressed_size,
+                                data_size,
+                            ),
+
+                            blacklisted: Vec::new(),
+                            sha: None,
+                        })
+                    } else {
+                        return Err(Box::new(BSPError::InvalidPakFile(
+                            header_pos + 8,
+                        )));
+                    }
+                } else {
+                    // STORE
+
+                    let data_size = read_u32(header, 22);
+                    let name_size = read_u16(header, 26);
+                    let extra_size = read_u16(header, 28);
+
+                    // let name = unsafe {
+                    //     std::str::from_utf8_unchecked(
+                    //         &file[position..position + name_size as usize],
+                    //     )
+                    // };
+                    let name = (position as u32, name_size as u32);
+                    position += name_size as usize;
+                    position += extra_size as usize;
+
+                    // let data = &file[position..position + data_size as usize];
+                    if position + data_size as usize > file.len() {
+                        return Err(Box::new(BSPError::InvalidPakFile(header_pos + 22)));
+                    }
+                    let data = (position as u32, data_size);
+                    position += data_size as usize;
+
+                    files.push(PakFile {
+                        name,
+                        data,
+                        remove: false,
+
+                        real_data: None,
+                        compression_algo: PakAlgo::None,
+
+                        blacklisted: Vec::new(),
+                        sha: None,
+                    })
+                }
+            }
+            &[0x50, 0x4B, 1, 2] => {
+                break; // Central directory aka ending stuff
+            }
+            _ => {
+                return Err(Box::new(BSPError::InvalidPakFile(header_pos)));
+            }
+        }
+    }
+
+    Ok(BSPLump::PakFile(PakFileLump { base, files }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_f32s(buf: &mut Vec<u8>, values: &[f32]) {
+        for v in values {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    fn push_i32s(buf: &mut Vec<u8>, values: &[i32]) {
+        for v in values {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    // A spotlight as VRAD writes it, v1 has the shadow cast offset between the normal and the cluster
+    fn world_light(version: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push_f32s(&mut buf, &[1.0, 2.0, 3.0]); // origin
+        push_f32s(&mut buf, &[10.0, 20.0, 30.0]); // intensity
+        push_f32s(&mut buf, &[0.0, 0.0, -1.0]); // normal
+        if version != 0 {
+            push_f32s(&mut buf, &[7.0, 8.0, 9.0]); // shadow cast offset
+        }
+        push_i32s(&mut buf, &[5, 2, 0]); // cluster, type, style
+        push_f32s(&mut buf, &[0.9, 0.8, 1.0, 256.0]); // stopdot, stopdot2, exponent, radius
+        push_f32s(&mut buf, &[0.0, 0.0, 1.0]); // attenuation
+        push_i32s(&mut buf, &[1, -1, 0]); // flags, texinfo, owner
+        assert_eq!(buf.len(), WorldLight::size(version));
+        buf
+    }
+
+    #[test]
+    fn world_light_versions() {
+        for version in [0, 1] {
+            let light = WorldLight::read(&world_light(version), version);
+            assert_eq!(light.origin, [1.0, 2.0, 3.0]);
+            assert_eq!(light.intensity, [10.0, 20.0, 30.0]);
+            assert_eq!(light.normal, [0.0, 0.0, -1.0]);
+            assert_eq!(light.cluster, 5);
+            assert_eq!(light.kind, EmitType::Spotlight);
+            assert_eq!(light.radius, 256.0);
+            assert_eq!(light.attenuation, [0.0, 0.0, 1.0]);
+            assert_eq!((light.flags, light.texinfo, light.owner), (1, -1, 0));
+        }
+    }
+
+    #[test]
+    fn truncated_pakfile() {
+        // a STORE entry that claims 100 bytes of data but carries none
+        let mut buf = vec![0x50, 0x4B, 3, 4];
+        buf.resize(22, 0);
+        buf.extend_from_slice(&100u32.to_le_bytes());
+        buf.extend_from_slice(&[1, 0, 0, 0, b'a']);
+        let base = BasicLump { offset: 0, size: buf.len() as u32, version: 0, four: 0 };
+        assert!(parse_pakfile(base, &buf).is_err());
+
+        // and one whose header doesn't fit
+        let base = BasicLump { offset: 0, size: 10, version: 0, four: 0 };
+        assert!(parse_pakfile(base, &buf[..10]).is_err());
+    }
+
+    #[test]
+    fn lmp_round_trip() {
+        let lmp = write_lmp(40, 1, 2045, b"PK\x05\x06");
+        let (header, lump) = LmpHeader::read(&lmp).unwrap();
+        assert_eq!(
+            (header.offset, header.id, header.version, header.length, header.revision),
+            (LMP_HEADER_SIZE as i32, 40, 1, 4, 2045)
+        );
+        assert_eq!(lump, b"PK\x05\x06");
+
+        // shorter than the header says
+        assert!(LmpHeader::read(&lmp[..lmp.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn vis_decompress() {
+        // 20 clusters, 3 bytes a row: a literal, then a run of zeros that goes past the end of the row
+        let vis = VisLump {
+            base: BasicLump { offset: 0, size: 0, version: 0, four: 0 },
+            num_clusters: 20,
+            offsets: vec![[0, 0], [3, 3], [5, 5]],
+            data: vec![0x81, 0, 5, 0xFF, 0xFF, 0xF0, 0],
+        };
+        assert_eq!(vis.pvs(0), Some(vec![0x81, 0, 0]));
+        // literals only
+        assert_eq!(vis.pvs(1), Some(vec![0xFF, 0xFF, 0xF0]));
+        // run count missing at the end of the lump
+        assert_eq!(vis.pvs(2), None);
+        assert_eq!(vis.pvs(3), None);
+    }
 }
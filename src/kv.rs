@@ -66,6 +66,20 @@ impl Entity {
             format!("{}", classname)
         }
     }
+
+    // `"model" "*N"`, brush entities point into the models lump with it
+    pub fn brush_model(&self) -> Option<usize> {
+        self.get("model")?.strip_prefix('*')?.parse().ok()
+    }
+
+    // First `"key" "value"` line with that key, same one-pair-per-line assumption as above
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.string.lines().find_map(|line| {
+            let line = line.trim();
+            let rest = line.strip_prefix('"')?.strip_prefix(key)?.strip_prefix("\" \"")?;
+            rest.strip_suffix('"')
+        })
+    }
 }
 
 // TODO: deprecate?
@@ -3,12 +3,18 @@ use std::{cell::RefCell, io::Write, ops::Add, rc::Rc};
 use {egui_miniquad as egui_mq, miniquad as mq};
 
 mod blacklist;
+#[cfg(not(target_arch = "wasm32"))]
+mod blacklist_gen;
 mod bsp;
 mod kv;
 mod platform;
 mod vtf;
+#[cfg(not(target_arch = "wasm32"))]
+mod vpk;
 
+mod map_render;
 mod map_window;
+mod math;
 use bsp::BSPLump;
 use map_window::*;
 
@@ -18,7 +24,13 @@ struct Stage {
     maps: Vec<Rc<RefCell<MapWindowStage>>>,
     current_capture: Option<Rc<RefCell<MapWindowStage>>>,
 
-    blacklist: Option<blacklist::Blacklist>,
+    blacklists: blacklist::BlacklistSet,
+    show_blacklists: bool,
+    // how the last blacklist generation went, its thread fills this in when done
+    #[cfg(not(target_arch = "wasm32"))]
+    generator_status: std::sync::Arc<std::sync::Mutex<Option<Result<String, String>>>>,
+
+    last_update: f64,
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -34,7 +46,12 @@ impl Stage {
             maps: vec![],
             current_capture: None,
 
-            blacklist: None,
+            blacklists: Default::default(),
+            show_blacklists: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            generator_status: Default::default(),
+
+            last_update: mq::date::now(),
         }
     }
 
@@ -44,7 +61,11 @@ impl Stage {
         let egui_ctx = egui_mq.egui_ctx();
 
         let maps = &mut self.maps;
-        let blacklist_mut = &mut self.blacklist;
+        let blacklists = &mut self.blacklists;
+        let show_blacklists = &mut self.show_blacklists;
+        #[cfg(not(target_arch = "wasm32"))]
+        let generator_status = &self.generator_status;
+        let mut blacklists_changed = false;
 
         egui::TopBottomPanel::top("main_menu_bar").show(egui_ctx, |ui| {
             egui::menu::bar(ui, |ui| {
@@ -53,14 +74,14 @@ impl Stage {
                         // Open
                         #[cfg(not(target_arch = "wasm32"))]
                         if let Some(map_data) = platform::file_picker_map() {
-                            let (stem, vec) = map_data;
+                            let (stem, vec, lumps) = map_data;
 
                             #[cfg(debug_assertions)]
                             {
                                 println!("stem: {}", stem);
                             }
 
-                            match MapWindowStage::new(stem, vec, ctx, 256, 256, blacklist_mut.as_ref()) {
+                            match MapWindowStage::new(stem, vec, &lumps, ctx, 256, 256, blacklists) {
                                 Ok(v) =>{
                                     #[cfg(debug_assertions)]
                                     if let Some(v) = &v.parsed_map {
@@ -86,7 +107,7 @@ impl Stage {
                         }
                         #[cfg(target_arch = "wasm32")]
                         {
-                            platform::wasm_file_picker(maps as *mut std::vec::Vec<Rc<RefCell<map_window::MapWindowStage>>>, blacklist_mut as *mut Option<blacklist::Blacklist>, ctx as *mut mq::Context, platform::FileType::Map);
+                            platform::wasm_file_picker(maps as *mut std::vec::Vec<Rc<RefCell<map_window::MapWindowStage>>>, blacklists as *mut blacklist::BlacklistSet, ctx as *mut mq::Context, platform::FileType::Map);
                             unsafe {
                                 platform::console_log(std::ffi::CString::new(format!("{}", maps as *mut _ as u32)).unwrap().as_ptr());
                             };
@@ -94,14 +115,47 @@ impl Stage {
                     }
                     if ui.button("Load blacklist JSON").clicked() {
                         #[cfg(not(target_arch = "wasm32"))]
-                        if let Some((_, data)) = platform::file_picker_json() {
-                            if let Ok(blacklist) = blacklist::Blacklist::new(&data) {
-                                *blacklist_mut = Some(blacklist);
+                        if let Some((stem, data)) = platform::file_picker_json() {
+                            match blacklist::Blacklist::new(&data) {
+                                Ok(blacklist) => {
+                                    blacklists.add(stem, blacklist);
+                                    blacklists.load_error = None;
+                                    blacklists_changed = true;
+                                    *show_blacklists = true;
+                                }
+                                Err(v) => {
+                                    eprintln!("{:#?}", v);
+                                    blacklists.load_error = Some(format!("{}: {}", stem, v));
+                                    *show_blacklists = true;
+                                }
                             }
                         }
                         #[cfg(target_arch = "wasm32")]
                         {
-                            platform::wasm_file_picker(maps as *mut std::vec::Vec<Rc<RefCell<map_window::MapWindowStage>>>, blacklist_mut as *mut Option<blacklist::Blacklist>, ctx as *mut mq::Context, platform::FileType::Blacklist);
+                            platform::wasm_file_picker(maps as *mut std::vec::Vec<Rc<RefCell<map_window::MapWindowStage>>>, blacklists as *mut blacklist::BlacklistSet, ctx as *mut mq::Context, platform::FileType::Blacklist);
+                        }
+                    }
+                    ui.checkbox(show_blacklists, "Blacklists");
+                    #[cfg(not(target_arch = "wasm32"))]
+                    for (prefix, label) in &[("game_", "Generate game blacklist"), ("pack_", "Generate pack blacklist")] {
+                        if ui.button(*label).clicked() {
+                            if let Some(root) = platform::folder_picker() {
+                                let name = format!("{}{}", prefix, root.file_name().map(|f| f.to_string_lossy().to_lowercase()).unwrap_or_default());
+                                // picking an existing JSON reuses its cache, so only patched files get re-hashed
+                                if let Some(output) = platform::save_path_picker("JSON", &["json"]) {
+                                    *generator_status.lock().unwrap() = Some(Ok(format!("Generating {}...", name)));
+                                    *show_blacklists = true;
+                                    let status = generator_status.clone();
+                                    // full game installs take a while, don't freeze the UI
+                                    std::thread::spawn(move || {
+                                        let result = match blacklist_gen::generate(&root, name.clone(), &output) {
+                                            Ok(v) => Ok(format!("{}: {} hashed, {} cached, {} failed -> {}", v.name, v.hashed, v.reused, v.failed, output.display())),
+                                            Err(v) => Err(format!("Failed to generate {}: {}", name, v)),
+                                        };
+                                        *status.lock().unwrap() = Some(result);
+                                    });
+                                }
+                            }
                         }
                     }
                     #[cfg(not(target_arch = "wasm32"))]
@@ -126,12 +180,31 @@ impl Stage {
                         let textures = &map.textures;
 
                         let mut show = map.open; // hack to work around borrow problems
+                        let mut views = map.views;
+                        let mut compress_entities = map.compress_entities;
+                        let mut save_error = map.save_error.clone();
 
                         egui::menu::menu(ui, title, |ui| {
                             ui.checkbox(&mut show, "Views");
+                            ui.checkbox(&mut views.materials, "Materials");
+                            ui.checkbox(&mut views.props, "Props");
+                            ui.checkbox(&mut views.displacements, "Displacements");
+                            ui.checkbox(&mut views.brushes, "Brushes");
+                            ui.checkbox(&mut views.cubemaps, "Cubemaps");
+                            ui.checkbox(&mut views.overlays, "Overlays");
+                            ui.checkbox(&mut views.lights, "Lights");
+                            ui.checkbox(&mut views.physics, "Physics");
+                            ui.checkbox(&mut compress_entities, "Compress entities on save");
                             if ui.button("Save").clicked() {
+                                save_error = None;
                                 // brih...
-                                if let Some(parsed_map) = parsed_map {
+                                if let Some(parsed_map) = parsed_map.as_ref().filter(|f| {
+                                    // overridden lumps live past the end of the original file, in place saving can't work
+                                    if !f.overrides.is_empty() {
+                                        save_error = Some("Lumps are overridden by .lmp files, export those instead of saving the map".to_string());
+                                    }
+                                    f.overrides.is_empty()
+                                }) {
                                     // was used when trying to properly serialise the map...
                                     // let total_lump_sizes = parsed_map.lumps.as_ref().iter().map(|f| match f {
                                     //     BSPLump::Entities(v) => v.base.size,
@@ -142,8 +215,10 @@ impl Stage {
 
                                     let entlump = lump_helper!(&parsed_map.lumps[0], BSPLump::Entities(v) => v);
                                     let entity_data_str = entities.iter().map(|f| f.string.as_str()).collect::<Vec<&str>>().join("\n").add("\n\0");
-                                    let (entity_data, ent_vec) = if entities.iter().find(|f| f.dirty).is_some() {
-                                        if entlump.base.four == 0 {
+                                    let dirty = entities.iter().any(|f| f.dirty);
+                                    let rewrite = dirty || compress_entities != parsed_map.compressed[0];
+                                    let (entity_data, ent_vec) = if rewrite {
+                                        if !compress_entities {
                                             (entity_data_str.as_bytes(), None)
                                         } else {
                                             // we have compressed data :/
@@ -167,112 +242,9 @@ impl Stage {
                                     };
 
                                     // TODO: recreate array
-                                    // TODO: remove material?
                                     let paklump = lump_helper!(&parsed_map.lumps[40], BSPLump::PakFile(v) => v);
-                                    let pakbuf = paklump.data(&parsed_map.buf);
-                                    let mut pakfiles = paklump.files.clone();
-                                    for texture in textures {
-                                        if texture.to_remove {
-                                            let name = texture.name.as_str();
-                                            if let Some(v) = pakfiles.iter_mut().find(|f| {
-                                                f.name(pakbuf).eq(name)
-                                            }) {
-                                                v.remove = true;
-                                            }
-                                        }
-                                    }
-                                    // TODO: REFACTOR INTO A FUNCTION!
-                                    let (mut zip_data, lzma_pass) = if pakfiles.iter().find(|f| f.remove).is_some() {
-                                        let zipw = Vec::<u8>::new();
-                                        let zipc = std::io::Cursor::new(zipw);
-                                        let mut zip_writer = zip::ZipWriter::new(zipc);
-                                        let options = zip::write::FileOptions::default()
-                                            .compression_method(zip::CompressionMethod::Stored) // force store to be extra safe
-                                            .unix_permissions(0o755);
-                                        let mut lzma_pass = false;
-                                        for pakfile in &pakfiles {
-                                            if !pakfile.remove {
-                                                zip_writer.start_file(pakfile.name(pakbuf), options).unwrap();
-                                                match pakfile.compression_algo {
-                                                    bsp::PakAlgo::None => {
-                                                        zip_writer.write_all(pakfile.data(pakbuf)).unwrap();
-                                                    },
-                                                    // We care for compressed size because I don't want to go over it if I just recompress...
-                                                    bsp::PakAlgo::LZMA(comp, _) => {
-                                                        let data = pakfile.data(&[]);
-
-                                                        lzma_pass = true;
-
-                                                        let comp_data = pakfile.data;
-                                                        zip_writer.write_all(&pakbuf[comp_data.0 as usize..(comp_data.0+comp_data.1) as usize]).unwrap();
-
-                                                        // for future?
-                                                        // let output = gmod_lzma::compress_valve(data, 9 | (1 << 31)).unwrap();
-
-                                                        // eprintln!("LZMA comp: {} V {}", comp, output.len());
-                                                        // #[cfg(debug_assertions)]
-                                                        // assert!(comp as usize >= output.len());
-
-                                                        // // Version + props size as per LZMA in ZIP spec
-                                                        // zip_writer.write_all(&[0x09, 0x26, 5, 0]).unwrap();
-                                                        // zip_writer.write_all(output.as_slice()).unwrap();
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        (zip_writer.finish().unwrap(), lzma_pass)
-                                    } else {
-                                        (std::io::Cursor::new(pakbuf.to_vec()), false)
-                                    };
-                                    let pak_data_mut = zip_data.get_mut().as_mut_slice();
-                                    // OMG why
-                                    if lzma_pass {
-                                        let mut position = 0usize;
-                                        loop {
-                                            let header_pos = position;
-                                            let header = &pak_data_mut[position..position + 30];
-                                            position += 30;
-                                            match &header[0..4] {
-                                                &[0x50, 0x4B, 3, 4] => {
-                                                    let compressed_size =
-                                                        unsafe { *((&header[18..22]).as_ptr().cast::<u32>()) };
-                                                    // let data_size =
-                                                    //     unsafe { *((&header[22..26]).as_ptr().cast::<u32>()) };
-                                                    let name_size =
-                                                        unsafe { *((&header[26..28]).as_ptr().cast::<u16>()) };
-                                                    let extra_size =
-                                                        unsafe { *((&header[28..30]).as_ptr().cast::<u16>()) };
-                                                    let name = (position as u32, name_size as u32);
-                                                    position += name_size as usize;
-                                                    position += extra_size as usize;
-                                                    position += compressed_size as usize;
-                                                    for pakfile in &pakfiles {
-                                                        if !pakfile.remove {
-                                                            match pakfile.compression_algo {
-                                                                bsp::PakAlgo::LZMA(_, _) => {
-                                                                    if pakfile.name(pakbuf).as_bytes() == &pak_data_mut[name.0 as usize..(name.0 + name.1) as usize] {
-                                                                        // we got a hit for LZMA packed file, yay?
-                                                                        pak_data_mut[header_pos + 8] = 0xE;
-                                                                        pak_data_mut[header_pos + 22..header_pos + 26].copy_from_slice(&(pakfile.data(&[]).len() as u32).to_le_bytes());
-                                                                    }
-                                                                },
-                                                                _ => {
-                                                                    // do nothing...
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                },
-                                                &[0x50, 0x4B, 1, 2] => {
-                                                    break; // Central directory aka ending stuff
-                                                }
-                                                _ => {
-                                                    unreachable!();
-                                                }
-                                            }
-                                        }
-                                    }
-                                    let pak_data = pak_data_mut.as_ref();
+                                    let pak_data = rebuild_pak(paklump, &parsed_map.buf, textures);
+                                    let pak_data = pak_data.as_slice();
 
                                     let mut lumps_temp = [0u32; 64]; // offset
                                     {
@@ -287,7 +259,7 @@ impl Stage {
                                                     accum += pak_data.len() as u32;
                                                 }
                                                 v => {
-                                                    let sz = lump_helper!(&parsed_map.lumps[v as usize], BSPLump::Unknown(l) => l).size;
+                                                    let sz = parsed_map.lumps[v as usize].base().size;
                                                     accum += sz;
                                                     if sz == 0 {
                                                         lumps_temp[v as usize] = 0;
@@ -371,18 +343,50 @@ impl Stage {
                                             }
                                         }
 
-                                        platform::save_picker(&title, &["bsp"], &buf);
+                                        // the header's fourth field is the uncompressed size of LZMA'd lumps and 0 otherwise,
+                                        // same spot in both v21 layouts
+                                        let four = match (&ent_vec, rewrite) {
+                                            (Some(_), _) => entity_data_str.len() as u32,
+                                            (None, true) => 0,
+                                            (None, false) => entlump.base.four,
+                                        };
+                                        buf[8 + 12..8 + 16].copy_from_slice(&four.to_le_bytes());
+
+                                        if let Err(v) = platform::save_picker(&title, &["bsp"], &buf) {
+                                            save_error = Some(v.to_string());
+                                        }
                                     } else {
-                                        eprintln!("Failed: {} ({} V {}) | {} ({} V {})", can_ent, (entity_data.len() as u32), entlump.base.size, can_pak, (pak_data.len() as u32), paklump.base.size);
+                                        save_error = Some(format!(
+                                            "Edits don't fit the original lumps: entities {} of {} bytes, pak {} of {} bytes",
+                                            entity_data.len(), entlump.base.size, pak_data.len(), paklump.base.size
+                                        ));
+                                    }
+                                }
+                            }
+                            // the pak with removed textures left out, as `<map>_l_40.lmp` it works even when Save can't
+                            if ui.button("Export pak as .lmp").clicked() {
+                                save_error = None;
+                                if let Some(parsed_map) = parsed_map.as_ref() {
+                                    let paklump = lump_helper!(&parsed_map.lumps[40], BSPLump::PakFile(v) => v);
+                                    let pak_data = rebuild_pak(paklump, &parsed_map.buf, textures);
+                                    let lmp = bsp::write_lmp(40, paklump.base.version, parsed_map.iteration, &pak_data);
+                                    if let Err(v) = platform::save_picker("Lump file", &["lmp"], &lmp) {
+                                        save_error = Some(v.to_string());
                                     }
                                 }
                             }
+                            if let Some(v) = &save_error {
+                                ui.colored_label(egui::color::Color32::RED, v);
+                            }
                             if ui.button("Close").clicked() {
                                 ret = Some(i);
                             }
                         });
 
                         map.open = show;
+                        map.views = views;
+                        map.compress_entities = compress_entities;
+                        map.save_error = save_error;
                     }
 
                     ret
@@ -393,12 +397,71 @@ impl Stage {
                             i.texture.delete()
                         }
                     }
+                    if let Some(renderer) = &a.borrow().renderer {
+                        renderer.delete();
+                    }
+                    a.borrow().offscreen.delete(ctx);
 
                     maps.remove(remove_index);
                 };
             });
         });
 
+        egui::Window::new("Blacklists")
+            .open(show_blacklists)
+            .resizable(true)
+            .show(egui_ctx, |ui| {
+                if let Some(error) = &blacklists.load_error {
+                    ui.colored_label(egui::color::Color32::RED, format!("Failed to load {}", error));
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                match &*generator_status.lock().unwrap() {
+                    Some(Ok(v)) => {
+                        ui.label(v);
+                    }
+                    Some(Err(v)) => {
+                        ui.colored_label(egui::color::Color32::RED, v);
+                    }
+                    None => {}
+                }
+                if blacklists.lists.is_empty() {
+                    ui.label("No blacklists loaded");
+                }
+                let mut remove = None;
+                for (i, list) in blacklists.lists.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.checkbox(&mut list.enabled, &list.name).changed() {
+                            blacklists_changed = true;
+                        }
+                        ui.label("Priority");
+                        if ui.add(egui::DragValue::new(&mut list.priority)).changed() {
+                            blacklists_changed = true;
+                        }
+                        ui.label(format!("{} hashes", list.blacklist.entry_count()));
+                        if list.blacklist.skipped > 0 {
+                            ui.colored_label(
+                                egui::color::Color32::YELLOW,
+                                format!("{} malformed entries skipped", list.blacklist.skipped),
+                            );
+                        }
+                        if ui.button("Remove").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    blacklists.lists.remove(i);
+                    blacklists_changed = true;
+                }
+            });
+
+        // maps opened before a blacklist got loaded need to be checked too
+        if blacklists_changed {
+            for map in maps.iter() {
+                map.borrow_mut().recheck(blacklists);
+            }
+        }
+
         for map in &self.maps {
             if map.borrow_mut().ui(&egui_ctx) {
                 if self.current_capture.is_none() {
@@ -415,7 +478,16 @@ impl Stage {
 }
 
 impl mq::EventHandler for Stage {
-    fn update(&mut self, _ctx: &mut mq::Context) {}
+    fn update(&mut self, _ctx: &mut mq::Context) {
+        let now = mq::date::now();
+        // don't teleport after a hitch or a minimised window
+        let dt = (now - self.last_update).min(0.1) as f32;
+        self.last_update = now;
+
+        if let Some(current_capture) = &self.current_capture {
+            current_capture.borrow_mut().fly(dt);
+        }
+    }
 
     fn draw(&mut self, ctx: &mut mq::Context) {
         ctx.clear(Some((1., 1., 1., 1.)), None, None);
@@ -440,11 +512,26 @@ impl mq::EventHandler for Stage {
     }
 
     fn mouse_motion_event(&mut self, ctx: &mut mq::Context, x: f32, y: f32) {
-        self.egui_mq.mouse_motion_event(ctx, x, y);
+        if self.current_capture.is_none() {
+            self.egui_mq.mouse_motion_event(ctx, x, y);
+        }
+    }
+
+    // the cursor is grabbed while captured, so only the raw deltas mean anything
+    fn raw_mouse_motion(&mut self, _ctx: &mut mq::Context, dx: f32, dy: f32) {
+        if let Some(current_capture) = &self.current_capture {
+            current_capture.borrow_mut().camera.rotate(dx, dy);
+        }
     }
 
     fn mouse_wheel_event(&mut self, ctx: &mut mq::Context, dx: f32, dy: f32) {
-        self.egui_mq.mouse_wheel_event(ctx, dx, dy);
+        if let Some(current_capture) = &self.current_capture {
+            let mut map = current_capture.borrow_mut();
+            let factor = if dy > 0.0 { 1.25 } else { 0.8 };
+            map.fly_speed = (map.fly_speed * factor).max(16.0).min(16384.0);
+        } else {
+            self.egui_mq.mouse_wheel_event(ctx, dx, dy);
+        }
     }
 
     fn mouse_button_down_event(
@@ -454,6 +541,12 @@ impl mq::EventHandler for Stage {
         x: f32,
         y: f32,
     ) {
+        if let Some(current_capture) = &self.current_capture {
+            if mb == mq::MouseButton::Left {
+                current_capture.borrow_mut().pick_entity();
+            }
+            return;
+        }
         self.egui_mq.mouse_button_down_event(ctx, mb, x, y);
     }
 
@@ -474,7 +567,9 @@ impl mq::EventHandler for Stage {
         _keymods: mq::KeyMods,
         _repeat: bool,
     ) {
-        self.egui_mq.char_event(character);
+        if self.current_capture.is_none() {
+            self.egui_mq.char_event(character);
+        }
     }
 
     fn key_down_event(
@@ -486,7 +581,10 @@ impl mq::EventHandler for Stage {
     ) {
         // hacky way to work around weird mouse lock in native
         if keycode == mq::KeyCode::Escape {
-            self.current_capture = None;
+            // don't keep flying off on keys that were held when released
+            if let Some(current_capture) = self.current_capture.take() {
+                current_capture.borrow_mut().fly = Default::default();
+            }
 
             // brih
             ctx.set_cursor_grab(true);
@@ -494,17 +592,126 @@ impl mq::EventHandler for Stage {
 
             ctx.show_mouse(true);
         } else if let Some(current_capture) = &self.current_capture {
-            // input handling code for map view here...
+            // egui doesn't get to see keys while flying, it would type them into whatever has focus
+            current_capture.borrow_mut().fly.set(keycode, true);
+            return;
         }
 
         self.egui_mq.key_down_event(ctx, keycode, keymods);
     }
 
     fn key_up_event(&mut self, _ctx: &mut mq::Context, keycode: mq::KeyCode, keymods: mq::KeyMods) {
+        if let Some(current_capture) = &self.current_capture {
+            current_capture.borrow_mut().fly.set(keycode, false);
+        }
         self.egui_mq.key_up_event(keycode, keymods);
     }
 }
 
+// The pak lump with every texture marked for removal left out, or a copy of it when nothing is
+fn rebuild_pak(paklump: &bsp::PakFileLump, buf: &[u8], textures: &[Texture]) -> Vec<u8> {
+    // TODO: remove material?
+    let pakbuf = paklump.data(buf);
+    let mut pakfiles = paklump.files.clone();
+    for texture in textures {
+        if texture.to_remove {
+            let name = texture.name.as_str();
+            if let Some(v) = pakfiles.iter_mut().find(|f| {
+                f.name(pakbuf).eq(name)
+            }) {
+                v.remove = true;
+            }
+        }
+    }
+    let (mut zip_data, lzma_pass) = if pakfiles.iter().find(|f| f.remove).is_some() {
+        let zipw = Vec::<u8>::new();
+        let zipc = std::io::Cursor::new(zipw);
+        let mut zip_writer = zip::ZipWriter::new(zipc);
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored) // force store to be extra safe
+            .unix_permissions(0o755);
+        let mut lzma_pass = false;
+        for pakfile in &pakfiles {
+            if !pakfile.remove {
+                zip_writer.start_file(pakfile.name(pakbuf), options).unwrap();
+                match pakfile.compression_algo {
+                    bsp::PakAlgo::None => {
+                        zip_writer.write_all(pakfile.data(pakbuf)).unwrap();
+                    },
+                    // We care for compressed size because I don't want to go over it if I just recompress...
+                    bsp::PakAlgo::LZMA(comp, _) => {
+                        let data = pakfile.data(&[]);
+
+                        lzma_pass = true;
+
+                        let comp_data = pakfile.data;
+                        zip_writer.write_all(&pakbuf[comp_data.0 as usize..(comp_data.0+comp_data.1) as usize]).unwrap();
+
+                        // for future?
+                        // let output = gmod_lzma::compress_valve(data, 9 | (1 << 31)).unwrap();
+
+                        // eprintln!("LZMA comp: {} V {}", comp, output.len());
+                        // #[cfg(debug_assertions)]
+                        // assert!(comp as usize >= output.len());
+
+                        // // Version + props size as per LZMA in ZIP spec
+                        // zip_writer.write_all(&[0x09, 0x26, 5, 0]).unwrap();
+                        // zip_writer.write_all(output.as_slice()).unwrap();
+                    }
+                }
+            }
+        }
+        (zip_writer.finish().unwrap(), lzma_pass)
+    } else {
+        (std::io::Cursor::new(pakbuf.to_vec()), false)
+    };
+    let pak_data_mut = zip_data.get_mut().as_mut_slice();
+    // OMG why
+    if lzma_pass {
+        let mut position = 0usize;
+        loop {
+            let header_pos = position;
+            let header = &pak_data_mut[position..position + 30];
+            position += 30;
+            match &header[0..4] {
+                &[0x50, 0x4B, 3, 4] => {
+                    let compressed_size = bsp::read_u32(header, 18);
+                    // let data_size = bsp::read_u32(header, 22);
+                    let name_size = bsp::read_u16(header, 26);
+                    let extra_size = bsp::read_u16(header, 28);
+                    let name = (position as u32, name_size as u32);
+                    position += name_size as usize;
+                    position += extra_size as usize;
+                    position += compressed_size as usize;
+                    for pakfile in &pakfiles {
+                        if !pakfile.remove {
+                            match pakfile.compression_algo {
+                                bsp::PakAlgo::LZMA(_, _) => {
+                                    if pakfile.name(pakbuf).as_bytes() == &pak_data_mut[name.0 as usize..(name.0 + name.1) as usize] {
+                                        // we got a hit for LZMA packed file, yay?
+                                        pak_data_mut[header_pos + 8] = 0xE;
+                                        pak_data_mut[header_pos + 22..header_pos + 26].copy_from_slice(&(pakfile.data(&[]).len() as u32).to_le_bytes());
+                                    }
+                                },
+                                _ => {
+                                    // do nothing...
+                                }
+                            }
+                        }
+                    }
+                },
+                &[0x50, 0x4B, 1, 2] => {
+                    break; // Central directory aka ending stuff
+                }
+                _ => {
+                    unreachable!();
+                }
+            }
+        }
+    }
+    zip_data.into_inner()
+}
+
 fn main() {
     let conf = mq::conf::Conf {
         high_dpi: true,
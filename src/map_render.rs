@@ -0,0 +1,1356 @@
+use miniquad as mq;
+
+use crate::{
+    bsp::{
+        Brush, EmitType, Face, ParsedMap, Plane, TexInfo, WorldLight, OCCLUDER_FLAGS_INACTIVE, OVERLAY_MAX_FACES, WATER_OVERLAY_MAX_FACES, SURF_HINT, SURF_NODRAW, SURF_NOLIGHT, SURF_SKIP, SURF_SKY,
+        SURF_SKY2D, SURF_TRIGGER,
+    },
+    kv::Entity,
+    math::{self, Mat4, Vec3},
+};
+
+// Faces we never want to see in the shaded view
+pub const SURF_TOOLS: i32 = SURF_SKY2D | SURF_SKY | SURF_TRIGGER | SURF_NODRAW | SURF_HINT | SURF_SKIP;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub pos: Vec3,
+    pub normal: Vec3,
+    pub color: [f32; 4],
+    pub lightmap: [f32; 2], // atlas UV
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Flat,
+    Lightmapped,
+    LightmapOnly, // no VRAD shows up as plain white
+    Wireframe,    // flat with the triangles drawn over it
+    Normals,
+    Flags,         // tool faces included, coloured by their texinfo flags
+    Displacements, // nothing but displacements
+}
+
+impl RenderMode {
+    pub const ALL: [Self; 7] = [
+        Self::Flat,
+        Self::Lightmapped,
+        Self::LightmapOnly,
+        Self::Wireframe,
+        Self::Normals,
+        Self::Flags,
+        Self::Displacements,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Flat => "Flat",
+            Self::Lightmapped => "Lightmapped",
+            Self::LightmapOnly => "Lightmap only",
+            Self::Wireframe => "Wireframe",
+            Self::Normals => "Normals",
+            Self::Flags => "Surface flags",
+            Self::Displacements => "Displacements",
+        }
+    }
+
+    pub fn world_style(&self) -> WorldStyle {
+        match self {
+            Self::Flags => WorldStyle::Flags,
+            Self::Displacements => WorldStyle::Displacements,
+            _ => WorldStyle::Shaded,
+        }
+    }
+
+    fn shader_mode(&self) -> f32 {
+        match self {
+            Self::Lightmapped => shader::LIGHTMAPPED,
+            Self::LightmapOnly => shader::LIGHTMAP_ONLY,
+            Self::Normals => shader::NORMALS,
+            _ => shader::FLAT,
+        }
+    }
+}
+
+// What the world mesh has to be rebuilt for, modes that only differ in the shader share one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorldStyle {
+    Shaded,
+    Flags,
+    Displacements,
+}
+
+// Roughly the colours of the matching tool textures, first match wins
+pub const FLAG_COLOURS: [(&str, i32, [f32; 4]); 5] = [
+    ("Sky", SURF_SKY | SURF_SKY2D, [0.3, 0.7, 1.0, 1.0]),
+    ("Trigger", SURF_TRIGGER, [1.0, 0.55, 0.1, 1.0]),
+    ("Hint/skip", SURF_HINT | SURF_SKIP, [0.85, 0.3, 0.9, 1.0]),
+    ("Nodraw", SURF_NODRAW, [1.0, 0.9, 0.2, 1.0]),
+    ("No lightmap", SURF_NOLIGHT, [0.4, 0.9, 0.4, 1.0]),
+];
+const FLAG_DEFAULT_COLOUR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+// miniquad only does u16 indices, so bigger meshes get split into chunks
+#[derive(Debug)]
+pub struct MeshBuilder<V> {
+    pub chunks: Vec<(Vec<V>, Vec<u16>)>,
+}
+
+impl<V: Copy> MeshBuilder<V> {
+    pub fn new() -> Self {
+        Self {
+            chunks: vec![(Vec::new(), Vec::new())],
+        }
+    }
+
+    fn reserve(&mut self, count: usize) -> &mut (Vec<V>, Vec<u16>) {
+        if self.chunks.last().map(|f| f.0.len() + count).unwrap_or(0) > u16::MAX as usize {
+            self.chunks.push((Vec::new(), Vec::new()));
+        }
+        self.chunks.last_mut().unwrap()
+    }
+
+    // BSP faces are convex, a fan is enough
+    pub fn polygon(&mut self, vertices: &[V]) {
+        if vertices.len() < 3 || vertices.len() > u16::MAX as usize {
+            return;
+        }
+        let (v, i) = self.reserve(vertices.len());
+        let start = v.len() as u16;
+        v.extend_from_slice(vertices);
+        for k in 1..vertices.len() as u16 - 1 {
+            i.extend_from_slice(&[start, start + k, start + k + 1]);
+        }
+    }
+
+    // size*size vertices row by row, diagonals alternate like the engine does
+    pub fn grid(&mut self, vertices: &[V], size: usize) {
+        if size < 2 || vertices.len() != size * size || vertices.len() > u16::MAX as usize {
+            return;
+        }
+        let (v, i) = self.reserve(vertices.len());
+        let start = v.len();
+        v.extend_from_slice(vertices);
+        for row in 0..size - 1 {
+            for col in 0..size - 1 {
+                let a = (start + row * size + col) as u16;
+                let b = a + 1;
+                let c = a + size as u16;
+                let d = c + 1;
+                if (row + col) % 2 == 0 {
+                    i.extend_from_slice(&[a, c, d, a, d, b]);
+                } else {
+                    i.extend_from_slice(&[a, c, b, b, c, d]);
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.iter().all(|f| f.1.is_empty())
+    }
+}
+
+// Every triangle edge as a line, shared edges end up drawn twice but that doesn't show
+fn wire_indices(triangles: &[u16]) -> Vec<u16> {
+    triangles
+        .chunks_exact(3)
+        .flat_map(|f| vec![f[0], f[1], f[1], f[2], f[2], f[0]])
+        .collect()
+}
+
+pub struct GpuMesh {
+    pub chunks: Vec<(mq::Bindings, i32)>,
+    pub wire: Vec<(mq::Bindings, i32)>, // same vertex buffers, line indices
+}
+
+impl std::fmt::Debug for GpuMesh {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GpuMesh({} chunks)", self.chunks.len())
+    }
+}
+
+impl GpuMesh {
+    pub fn empty() -> Self {
+        Self {
+            chunks: Vec::new(),
+            wire: Vec::new(),
+        }
+    }
+
+    // `wire` also uploads line indices for `draw_wire`
+    pub fn new<V>(ctx: &mut mq::Context, builder: &MeshBuilder<V>, images: &[mq::Texture], wire: bool) -> Self {
+        let mut ret = Self::empty();
+        for (v, i) in builder.chunks.iter().filter(|(_, i)| !i.is_empty()) {
+            let vertex_buffer = mq::Buffer::immutable(ctx, mq::BufferType::VertexBuffer, v);
+            let index_buffer = mq::Buffer::immutable(ctx, mq::BufferType::IndexBuffer, i);
+            ret.chunks.push((
+                mq::Bindings {
+                    vertex_buffers: vec![vertex_buffer],
+                    index_buffer,
+                    images: images.to_vec(),
+                },
+                i.len() as i32,
+            ));
+
+            if wire {
+                let lines = wire_indices(i);
+                let index_buffer = mq::Buffer::immutable(ctx, mq::BufferType::IndexBuffer, &lines);
+                ret.wire.push((
+                    mq::Bindings {
+                        vertex_buffers: vec![vertex_buffer],
+                        index_buffer,
+                        images: images.to_vec(),
+                    },
+                    lines.len() as i32,
+                ));
+            }
+        }
+        ret
+    }
+
+    pub fn draw(&self, ctx: &mut mq::Context) {
+        for (bindings, count) in &self.chunks {
+            ctx.apply_bindings(bindings);
+            ctx.draw(0, *count, 1);
+        }
+    }
+
+    // Needs a line pipeline applied
+    pub fn draw_wire(&self, ctx: &mut mq::Context) {
+        for (bindings, count) in &self.wire {
+            ctx.apply_bindings(bindings);
+            ctx.draw(0, *count, 1);
+        }
+    }
+
+    pub fn delete(&self) {
+        for (bindings, _) in &self.chunks {
+            for buffer in &bindings.vertex_buffers {
+                buffer.delete();
+            }
+            bindings.index_buffer.delete();
+        }
+        // vertex buffers are the ones above
+        for (bindings, _) in &self.wire {
+            bindings.index_buffer.delete();
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub pos: Vec3,
+    pub yaw: f32,   // radians, around Z
+    pub pitch: f32, // radians, up is positive
+    pub fov: f32,
+}
+
+impl Camera {
+    // Looking at the whole map from one of the corners
+    pub fn framing(mins: Vec3, maxs: Vec3) -> Self {
+        let center = math::scale(math::add(mins, maxs), 0.5);
+        let extent = math::sub(maxs, mins);
+        let pos = math::add(center, math::scale(extent, 0.5));
+        let dir = math::sub(center, pos);
+        Self {
+            pos,
+            yaw: dir[1].atan2(dir[0]),
+            pitch: dir[2].atan2((dir[0] * dir[0] + dir[1] * dir[1]).sqrt()),
+            fov: 75f32.to_radians(),
+        }
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        [
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        ]
+    }
+
+    // Z up, so yaw 0 looks down +X with +Y on the left
+    pub fn right(&self) -> Vec3 {
+        [self.yaw.sin(), -self.yaw.cos(), 0.0]
+    }
+
+    // Mouse deltas in pixels, moving the mouse right/down turns right/down
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        const SENSITIVITY: f32 = 0.0025;
+        const PITCH_LIMIT: f32 = 1.55; // just short of straight up/down, look_at breaks there
+
+        self.yaw = (self.yaw - dx * SENSITIVITY) % std::f32::consts::TAU;
+        self.pitch = (self.pitch - dy * SENSITIVITY).max(-PITCH_LIMIT).min(PITCH_LIMIT);
+    }
+
+    // Backs off along the current view direction until the box fits
+    pub fn look_at_box(&mut self, mins: Vec3, maxs: Vec3) {
+        let center = math::scale(math::add(mins, maxs), 0.5);
+        let radius = math::length(math::sub(maxs, mins)) / 2.0;
+        let distance = (radius / (self.fov / 2.0).sin()).max(64.0);
+        self.pos = math::sub(center, math::scale(self.forward(), distance));
+    }
+
+    pub fn view_proj(&self, aspect: f32) -> Mat4 {
+        let proj = math::perspective(self.fov, aspect, 4.0, 65536.0);
+        let view = math::look_at(self.pos, math::add(self.pos, self.forward()), [0.0, 0.0, 1.0]);
+        math::mul(&proj, &view)
+    }
+}
+
+// Reflectivity is what vrad uses for bounce light, so it's a decent "average colour" of the material
+pub fn material_colour(parsed_map: &ParsedMap, texinfo: &TexInfo) -> [f32; 4] {
+    if let Some(texdata) = parsed_map.tex_data().get(texinfo.texdata as usize) {
+        let [r, g, b] = texdata.reflectivity;
+        if r + g + b > 0.01 {
+            return [r.sqrt(), g.sqrt(), b.sqrt(), 1.0];
+        }
+    }
+
+    // no reflectivity, make one up that's at least stable per material
+    let hash = (texinfo.texdata as u32).wrapping_mul(2654435761);
+    [
+        0.3 + (hash & 0xFF) as f32 / 255.0 * 0.6,
+        0.3 + ((hash >> 8) & 0xFF) as f32 / 255.0 * 0.6,
+        0.3 + ((hash >> 16) & 0xFF) as f32 / 255.0 * 0.6,
+        1.0,
+    ]
+}
+
+pub fn face_normal(parsed_map: &ParsedMap, face: &Face) -> Vec3 {
+    let normal = parsed_map
+        .planes()
+        .get(face.plane as usize)
+        .map(|f| f.normal)
+        .unwrap_or([0.0, 0.0, 1.0]);
+    if face.side != 0 {
+        math::scale(normal, -1.0)
+    } else {
+        normal
+    }
+}
+
+// Per-vertex normals from the neighbouring grid points, terrain would look flat with the face normal
+fn grid_normals(positions: &[Vec3], size: usize, fallback: Vec3) -> Vec<Vec3> {
+    let at = |row: usize, col: usize| positions[row * size + col];
+    (0..size * size)
+        .map(|i| {
+            let (row, col) = (i / size, i % size);
+            let along_row = math::sub(at(row, (col + 1).min(size - 1)), at(row, col.saturating_sub(1)));
+            let along_col = math::sub(at((row + 1).min(size - 1), col), at(row.saturating_sub(1), col));
+            let normal = math::normalize(math::cross(along_row, along_col));
+            // same side as the base face, the winding doesn't tell us which way is up
+            if math::dot(normal, fallback) < 0.0 {
+                math::scale(normal, -1.0)
+            } else if math::length(normal) > 0.0 {
+                normal
+            } else {
+                fallback
+            }
+        })
+        .collect()
+}
+
+// RGBE sample to gamma corrected 8 bit, same curve the engine uses for LDR display
+fn decode_rgbe(sample: &[u8]) -> [u8; 3] {
+    let scale = 2f32.powi(sample[3] as i8 as i32) / 255.0;
+    let channel = |c: u8| ((c as f32 * scale).powf(1.0 / 2.2) * 255.0).min(255.0) as u8;
+    [channel(sample[0]), channel(sample[1]), channel(sample[2])]
+}
+
+// The leaf's ambient sample closest to `pos`, decoded per side. The engine blends a few of the
+// closest ones, for eyeballing whether props will come out black the nearest is plenty.
+pub fn sample_ambient(parsed_map: &ParsedMap, leaf: usize, pos: Vec3) -> Option<[[u8; 3]; 6]> {
+    let bounds = parsed_map.leafs().get(leaf)?;
+    let sample_pos = |frac: [u8; 3]| {
+        let mut out = [0.0; 3];
+        for i in 0..3 {
+            let (min, max) = (bounds.mins[i] as f32, bounds.maxs[i] as f32);
+            out[i] = min + (max - min) * frac[i] as f32 / 255.0;
+        }
+        out
+    };
+    let distance = |frac: [u8; 3]| math::length(math::sub(sample_pos(frac), pos));
+    let nearest = parsed_map
+        .leaf_ambient(leaf)
+        .iter()
+        .min_by(|a, b| distance(a.pos).partial_cmp(&distance(b.pos)).unwrap_or(std::cmp::Ordering::Equal))?;
+
+    let mut cube = [[0; 3]; 6];
+    for (side, sample) in cube.iter_mut().zip(nearest.cube.iter()) {
+        *side = decode_rgbe(sample);
+    }
+    Some(cube)
+}
+
+// Every face lightmap packed into one texture, rows of blocks sorted by height
+#[derive(Debug)]
+pub struct LightmapAtlas {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+    pub faces: Vec<Option<(u32, u32)>>, // top left sample per face, None is fullbright
+}
+
+impl LightmapAtlas {
+    const MAX_SIZE: u32 = 4096;
+    const BORDER: u32 = 1; // edge samples repeated so filtering doesn't bleed between faces
+    const WHITE: u32 = 4; // block in the corner for faces without a lightmap
+
+    pub fn new(parsed_map: &ParsedMap) -> Self {
+        let (lit_faces, lighting) = parsed_map.lighting();
+        let tex_info = parsed_map.tex_info();
+
+        // (face, samples wide, samples high, data)
+        let mut blocks = lit_faces
+            .iter()
+            .enumerate()
+            .filter_map(|(i, face)| {
+                let texinfo = tex_info.get(face.texinfo as usize)?;
+                if face.light_offset < 0 || texinfo.flags & SURF_NOLIGHT != 0 {
+                    return None;
+                }
+                let (w, h) = (face.lightmap_size[0] + 1, face.lightmap_size[1] + 1);
+                if w <= 0 || h <= 0 || w > 1024 || h > 1024 {
+                    return None;
+                }
+                let (w, h) = (w as u32, h as u32);
+                let start = face.light_offset as usize;
+                // bumped faces have 3 more maps after this one, the first is the unbumped one
+                let data = lighting.get(start..start + (w * h * 4) as usize)?;
+                Some((i, w, h, data))
+            })
+            .collect::<Vec<_>>();
+        blocks.sort_by_key(|f| std::cmp::Reverse(f.2));
+
+        let area = blocks
+            .iter()
+            .map(|f| ((f.1 + Self::BORDER * 2) * (f.2 + Self::BORDER * 2)) as u64)
+            .sum::<u64>();
+        // at least as wide as the widest block, shelves can't wrap a block onto the next row
+        let widest = blocks.iter().map(|f| f.1 + Self::BORDER * 2).max().unwrap_or(0);
+        let width = ((area as f64 * 1.2).sqrt() as u32)
+            .max(widest)
+            .next_power_of_two()
+            .max(256)
+            .min(Self::MAX_SIZE);
+
+        // shelf packing, good enough for lightmaps that are mostly tiny
+        let mut positions = Vec::with_capacity(blocks.len());
+        let (mut x, mut y, mut shelf) = (Self::WHITE, 0, Self::WHITE);
+        for (_, w, h, _) in &blocks {
+            let (bw, bh) = (w + Self::BORDER * 2, h + Self::BORDER * 2);
+            if x + bw > width {
+                x = 0;
+                y += shelf;
+                shelf = 0;
+            }
+            positions.push((x, y));
+            x += bw;
+            shelf = shelf.max(bh);
+        }
+        let height = (y + shelf).next_power_of_two().max(Self::WHITE);
+
+        let mut faces = vec![None; parsed_map.faces().len()];
+        if height > Self::MAX_SIZE {
+            eprintln!("Lightmaps don't fit in {0}x{0}, skipping them", Self::MAX_SIZE);
+            return Self::fullbright(faces);
+        }
+
+        let mut rgba = vec![0u8; (width * height * 4) as usize];
+        for y in 0..Self::WHITE {
+            for x in 0..Self::WHITE {
+                let i = ((y * width + x) * 4) as usize;
+                rgba[i..i + 4].copy_from_slice(&[255; 4]);
+            }
+        }
+
+        for ((face, w, h, data), (bx, by)) in blocks.iter().zip(positions) {
+            for y in 0..h + Self::BORDER * 2 {
+                for x in 0..w + Self::BORDER * 2 {
+                    // clamp into the sample grid to fill the border
+                    let sx = x.saturating_sub(Self::BORDER).min(w - 1);
+                    let sy = y.saturating_sub(Self::BORDER).min(h - 1);
+                    let sample = ((sy * w + sx) * 4) as usize;
+                    let [r, g, b] = decode_rgbe(&data[sample..sample + 4]);
+                    let i = (((by + y) * width + bx + x) * 4) as usize;
+                    rgba[i..i + 4].copy_from_slice(&[r, g, b, 255]);
+                }
+            }
+            if let Some(v) = faces.get_mut(*face) {
+                *v = Some((bx + Self::BORDER, by + Self::BORDER));
+            }
+        }
+
+        Self {
+            width,
+            height,
+            rgba,
+            faces,
+        }
+    }
+
+    fn fullbright(faces: Vec<Option<(u32, u32)>>) -> Self {
+        Self {
+            width: Self::WHITE,
+            height: Self::WHITE,
+            rgba: vec![255; (Self::WHITE * Self::WHITE * 4) as usize],
+            faces,
+        }
+    }
+
+    // Middle of the white block, for geometry that isn't lit
+    pub fn white_uv(&self) -> [f32; 2] {
+        let center = Self::WHITE as f32 / 2.0;
+        [center / self.width as f32, center / self.height as f32]
+    }
+
+    // `pos` is on the face plane (the flat base for displacements)
+    pub fn uv(&self, face_index: usize, face: &Face, texinfo: &TexInfo, pos: Vec3) -> [f32; 2] {
+        let (x, y) = match self.faces.get(face_index) {
+            Some(Some(v)) => *v,
+            _ => return self.white_uv(),
+        };
+        let project = |i: usize| {
+            let v = texinfo.lightmap_vecs[i];
+            math::dot(pos, [v[0], v[1], v[2]]) + v[3] - face.lightmap_mins[i] as f32
+        };
+        [
+            (x as f32 + project(0) + 0.5) / self.width as f32,
+            (y as f32 + project(1) + 0.5) / self.height as f32,
+        ]
+    }
+}
+
+// `visible` dims every face it has false for, used to show the PVS
+pub fn build_world(
+    parsed_map: &ParsedMap,
+    atlas: &LightmapAtlas,
+    visible: Option<&[bool]>,
+    style: WorldStyle,
+) -> (MeshBuilder<Vertex>, Vec3, Vec3) {
+    let mut builder = MeshBuilder::new();
+    let mut mins = [f32::MAX; 3];
+    let mut maxs = [f32::MIN; 3];
+    let mut extend = |pos: &Vec3| {
+        for i in 0..3 {
+            mins[i] = mins[i].min(pos[i]);
+            maxs[i] = maxs[i].max(pos[i]);
+        }
+    };
+
+    let tex_info = parsed_map.tex_info();
+    let (lit_faces, _) = parsed_map.lighting();
+    for (face_index, face) in parsed_map.faces().iter().enumerate() {
+        let lit_face = lit_faces.get(face_index).unwrap_or(face);
+        let texinfo = if let Some(v) = tex_info.get(face.texinfo as usize) {
+            v
+        } else {
+            continue;
+        };
+        let mut color = match style {
+            WorldStyle::Flags => FLAG_COLOURS
+                .iter()
+                .find(|(_, flags, _)| texinfo.flags & flags != 0)
+                .map(|f| f.2)
+                .unwrap_or(FLAG_DEFAULT_COLOUR),
+            _ if texinfo.flags & SURF_TOOLS != 0 => continue,
+            WorldStyle::Displacements if face.dispinfo < 0 => continue,
+            _ => material_colour(parsed_map, texinfo),
+        };
+        if let Some(visible) = visible {
+            if !visible.get(face_index).copied().unwrap_or(false) {
+                color = [color[0] * 0.15, color[1] * 0.15, color[2] * 0.2, color[3]];
+            }
+        }
+
+        // the base face of a displacement is never drawn, the grid replaces it
+        if face.dispinfo >= 0 {
+            let disp = parsed_map.disp_infos().get(face.dispinfo as usize);
+            let grid = disp.and_then(|f| parsed_map.disp_vertices(f));
+            if let (Some(disp), Some(grid)) = (disp, grid) {
+                let size = disp.size();
+                let positions = grid.iter().map(|f| f.pos).collect::<Vec<_>>();
+                let normals = grid_normals(&positions, size, face_normal(parsed_map, face));
+                let vertices = grid
+                    .iter()
+                    .zip(normals)
+                    .map(|(point, normal)| {
+                        extend(&point.pos);
+                        // darken blended areas a bit so the second material shows up
+                        let shade = 1.0 - point.alpha.max(0.0).min(255.0) / 255.0 * 0.4;
+                        Vertex {
+                            pos: point.pos,
+                            normal,
+                            color: [color[0] * shade, color[1] * shade, color[2] * shade, color[3]],
+                            lightmap: atlas.uv(face_index, lit_face, texinfo, point.base),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                builder.grid(&vertices, size);
+            }
+            continue;
+        }
+
+        let polygon = if let Some(v) = parsed_map.face_vertices(face) {
+            v
+        } else {
+            continue;
+        };
+
+        let normal = face_normal(parsed_map, face);
+        let vertices = polygon
+            .iter()
+            .map(|pos| {
+                extend(pos);
+                Vertex {
+                    pos: *pos,
+                    normal,
+                    color,
+                    lightmap: atlas.uv(face_index, lit_face, texinfo, *pos),
+                }
+            })
+            .collect::<Vec<_>>();
+        builder.polygon(&vertices);
+    }
+
+    (builder, mins, maxs)
+}
+
+const BRUSH_EXTENT: f32 = 65536.0; // bigger than any map, sides get clipped down from this
+const CLIP_EPSILON: f32 = 0.01;
+
+// Sutherland-Hodgman against one plane, keeps what's behind it
+fn clip_polygon(polygon: &[Vec3], plane: &Plane) -> Vec<Vec3> {
+    let distance = |p: Vec3| math::dot(p, plane.normal) - plane.dist;
+    let mut ret = Vec::with_capacity(polygon.len() + 1);
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let (da, db) = (distance(a), distance(b));
+        if da <= CLIP_EPSILON {
+            ret.push(a);
+        }
+        if (da > CLIP_EPSILON) != (db > CLIP_EPSILON) && (da - db).abs() > f32::EPSILON {
+            ret.push(math::lerp(a, b, da / (da - db)));
+        }
+    }
+    ret
+}
+
+// The shape of a brush, one polygon per non-bevel side, rebuilt from the planes since brushes keep no vertices
+pub fn brush_polygons(parsed_map: &ParsedMap, brush: &Brush) -> Vec<Vec<Vec3>> {
+    let planes = parsed_map.planes();
+    let sides = parsed_map
+        .sides_of(brush)
+        .iter()
+        .filter(|f| !f.bevel)
+        .filter_map(|f| planes.get(f.plane as usize))
+        .collect::<Vec<_>>();
+
+    sides
+        .iter()
+        .enumerate()
+        .filter_map(|(i, plane)| {
+            // huge quad on the plane, then cut it down by every other side
+            let up = if plane.normal[2].abs() > 0.9 {
+                [1.0, 0.0, 0.0]
+            } else {
+                [0.0, 0.0, 1.0]
+            };
+            let right = math::normalize(math::cross(up, plane.normal));
+            let up = math::cross(plane.normal, right);
+            let center = math::scale(plane.normal, plane.dist);
+            let (right, up) = (math::scale(right, BRUSH_EXTENT), math::scale(up, BRUSH_EXTENT));
+            let mut polygon = vec![
+                math::sub(math::sub(center, right), up),
+                math::sub(math::add(center, right), up),
+                math::add(math::add(center, right), up),
+                math::add(math::sub(center, right), up),
+            ];
+            for (k, other) in sides.iter().enumerate() {
+                if k != i {
+                    polygon = clip_polygon(&polygon, other);
+                }
+            }
+            if polygon.len() >= 3 {
+                Some(polygon)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// `brushes` are (brush index, colour), alpha is kept so the overlay can be see-through
+pub fn build_brush_overlay(parsed_map: &ParsedMap, brushes: &[(usize, [f32; 4])], white_uv: [f32; 2]) -> MeshBuilder<Vertex> {
+    let mut builder = MeshBuilder::new();
+    for (index, color) in brushes {
+        let brush = if let Some(v) = parsed_map.brushes().get(*index) {
+            v
+        } else {
+            continue;
+        };
+        for polygon in brush_polygons(parsed_map, brush) {
+            let normal = math::normalize(math::cross(
+                math::sub(polygon[1], polygon[0]),
+                math::sub(polygon[2], polygon[0]),
+            ));
+            let vertices = polygon
+                .iter()
+                .map(|pos| Vertex {
+                    pos: *pos,
+                    normal,
+                    color: *color,
+                    lightmap: white_uv,
+                })
+                .collect::<Vec<_>>();
+            builder.polygon(&vertices);
+        }
+    }
+    builder
+}
+
+const OVERLAY_OFFSET: f32 = 0.5; // off the face they're on so they don't z-fight
+
+// Overlays as flat quads in their material colour, broken ones in red
+pub fn build_overlays(parsed_map: &ParsedMap, white_uv: [f32; 2]) -> MeshBuilder<Vertex> {
+    let mut builder = MeshBuilder::new();
+    let face_total = parsed_map.faces().len();
+    let all = parsed_map
+        .overlays()
+        .iter()
+        .map(|f| (f, OVERLAY_MAX_FACES))
+        .chain(parsed_map.water_overlays().iter().map(|f| (f, WATER_OVERLAY_MAX_FACES)));
+    for (overlay, max_faces) in all {
+        let (invalid, too_many) = overlay.invalid_faces(face_total, max_faces);
+        let color = if !invalid.is_empty() || too_many {
+            [1.0, 0.1, 0.1, 0.8]
+        } else {
+            let [r, g, b, _] = parsed_map
+                .tex_info()
+                .get(overlay.texinfo as usize)
+                .map(|f| material_colour(parsed_map, f))
+                .unwrap_or([1.0; 4]);
+            [r, g, b, 0.8]
+        };
+
+        let normal = math::normalize(overlay.normal);
+        let offset = math::scale(normal, OVERLAY_OFFSET);
+        let vertices = overlay
+            .corners()
+            .iter()
+            .map(|pos| Vertex {
+                pos: math::add(*pos, offset),
+                normal,
+                color,
+                lightmap: white_uv,
+            })
+            .collect::<Vec<_>>();
+        builder.polygon(&vertices);
+    }
+    builder
+}
+
+const OCCLUDER_COLOUR: [f32; 4] = [0.2, 0.8, 1.0, 0.35];
+const INACTIVE_OCCLUDER_COLOUR: [f32; 4] = [0.6, 0.6, 0.6, 0.2]; // StartInactive ones, inputs can turn them on
+
+// func_occluder polygons, skipping any with vertex indices past the vertexes lump
+pub fn build_occluders(parsed_map: &ParsedMap, white_uv: [f32; 2]) -> MeshBuilder<Vertex> {
+    let mut builder = MeshBuilder::new();
+    let lump = if let Some(v) = parsed_map.occluders() {
+        v
+    } else {
+        return builder;
+    };
+    let vertices = parsed_map.vertices();
+    for occluder in &lump.occluders {
+        let color = if occluder.flags & OCCLUDER_FLAGS_INACTIVE != 0 {
+            INACTIVE_OCCLUDER_COLOUR
+        } else {
+            OCCLUDER_COLOUR
+        };
+        for poly in lump.polys_of(occluder) {
+            let points = lump
+                .indices_of(poly)
+                .iter()
+                .map(|f| vertices.get(*f as usize).copied())
+                .collect::<Option<Vec<_>>>();
+            let points = match points {
+                Some(v) if v.len() >= 3 => v,
+                _ => continue,
+            };
+            let normal = match parsed_map.planes().get(poly.plane as usize) {
+                Some(plane) => plane.normal,
+                None => math::normalize(math::cross(
+                    math::sub(points[1], points[0]),
+                    math::sub(points[2], points[0]),
+                )),
+            };
+            let vertices = points
+                .iter()
+                .map(|pos| Vertex {
+                    pos: *pos,
+                    normal,
+                    color,
+                    lightmap: white_uv,
+                })
+                .collect::<Vec<_>>();
+            builder.polygon(&vertices);
+        }
+    }
+    builder
+}
+
+const LIGHT_GIZMO_SIZE: f32 = 4.0; // half extent
+const SPOT_LENGTH: f32 = 48.0;
+
+// A box per light in its own colour, spotlights also get a spike where they point.
+// Sky lights have no position worth drawing.
+pub fn build_light_gizmos(lights: &[WorldLight], white_uv: [f32; 2]) -> MeshBuilder<Vertex> {
+    let mut builder = MeshBuilder::new();
+    for light in lights {
+        if let EmitType::Skylight | EmitType::SkyAmbient = light.kind {
+            continue;
+        }
+        // only the hue matters here, the brightness is all over the place
+        let [r, g, b] = light.intensity;
+        let max = r.max(g).max(b).max(f32::EPSILON);
+        let color = [r / max, g / max, b / max, 1.0];
+
+        let half = [LIGHT_GIZMO_SIZE; 3];
+        cuboid(&mut builder, math::sub(light.origin, half), math::add(light.origin, half), color, white_uv);
+
+        if light.kind == EmitType::Spotlight && math::length(light.normal) > 0.0 {
+            let dir = math::normalize(light.normal);
+            let side = if dir[2].abs() > 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 0.0, 1.0] };
+            let side = math::scale(math::normalize(math::cross(dir, side)), LIGHT_GIZMO_SIZE / 2.0);
+            let tip = math::add(light.origin, math::scale(dir, SPOT_LENGTH));
+            let vertex = |pos| Vertex {
+                pos,
+                normal: dir,
+                color,
+                lightmap: white_uv,
+            };
+            builder.polygon(&[
+                vertex(math::sub(light.origin, side)),
+                vertex(math::add(light.origin, side)),
+                vertex(tip),
+            ]);
+        }
+    }
+    builder
+}
+
+// Extra things drawn on top of the world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapLayers {
+    pub markers: bool,
+    pub overlays: bool,
+    pub lights: bool,
+    pub occluders: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntityMarker {
+    pub entity: usize, // index into MapWindowStage.entities
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub brush: bool, // bounds of a `"model" "*N"`, drawn as an outline
+    pub color: [f32; 4],
+}
+
+const POINT_MARKER_SIZE: f32 = 8.0; // half extent
+const OUTLINE_THICKNESS: f32 = 2.0;
+
+// Same families Hammer groups its entity list by, everything else gets a stable made up colour
+fn classname_colour(classname: &str) -> [f32; 4] {
+    const COLOURS: &[(&str, [f32; 4])] = &[
+        ("info_player_", [0.2, 1.0, 0.2, 1.0]),
+        ("info_", [0.4, 0.8, 0.4, 1.0]),
+        ("light", [1.0, 0.9, 0.3, 1.0]),
+        ("prop_", [0.3, 0.6, 1.0, 1.0]),
+        ("trigger_", [1.0, 0.5, 0.1, 1.0]),
+        ("func_", [0.2, 0.9, 0.9, 1.0]),
+        ("npc_", [1.0, 0.25, 0.25, 1.0]),
+        ("logic_", [0.8, 0.4, 1.0, 1.0]),
+        ("env_", [1.0, 0.4, 0.8, 1.0]),
+    ];
+    if let Some((_, colour)) = COLOURS.iter().find(|(prefix, _)| classname.starts_with(prefix)) {
+        return *colour;
+    }
+
+    let hash = classname
+        .bytes()
+        .fold(2166136261u32, |hash, f| (hash ^ f as u32).wrapping_mul(16777619));
+    [
+        0.4 + (hash & 0xFF) as f32 / 255.0 * 0.6,
+        0.4 + ((hash >> 8) & 0xFF) as f32 / 255.0 * 0.6,
+        0.4 + ((hash >> 16) & 0xFF) as f32 / 255.0 * 0.6,
+        1.0,
+    ]
+}
+
+fn parse_vec3(value: &str) -> Option<Vec3> {
+    let mut parts = value.split_whitespace().map(|f| f.parse::<f32>().ok());
+    Some([parts.next()??, parts.next()??, parts.next()??])
+}
+
+// Point entities get a small box at their origin, brush entities the bounds of their model
+pub fn entity_markers(parsed_map: &ParsedMap, entities: &[Entity]) -> Vec<EntityMarker> {
+    let models = parsed_map.models();
+    entities
+        .iter()
+        .enumerate()
+        .filter_map(|(i, entity)| {
+            let classname = entity.get("classname")?;
+            // the world is model 0 and already drawn
+            if classname == "worldspawn" {
+                return None;
+            }
+            let origin = entity.get("origin").and_then(parse_vec3);
+            let model = entity.brush_model().and_then(|f| models.get(f));
+
+            let (mins, maxs, brush) = match (model, origin) {
+                // brush model vertices are relative to the entity origin
+                (Some(model), origin) => {
+                    let origin = origin.unwrap_or([0.0; 3]);
+                    (math::add(model.mins, origin), math::add(model.maxs, origin), true)
+                }
+                (None, Some(origin)) => {
+                    let half = [POINT_MARKER_SIZE; 3];
+                    (math::sub(origin, half), math::add(origin, half), false)
+                }
+                (None, None) => return None,
+            };
+
+            Some(EntityMarker {
+                entity: i,
+                mins,
+                maxs,
+                brush,
+                color: classname_colour(classname),
+            })
+        })
+        .collect()
+}
+
+fn cuboid(builder: &mut MeshBuilder<Vertex>, mins: Vec3, maxs: Vec3, color: [f32; 4], lightmap: [f32; 2]) {
+    // (axis, side) of every box face, corners wound the same way around the normal
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for &side in &[false, true] {
+            let mut normal = [0.0; 3];
+            normal[axis] = if side { 1.0 } else { -1.0 };
+            let corner = |a: bool, b: bool| {
+                let mut pos = [0.0; 3];
+                pos[axis] = if side { maxs[axis] } else { mins[axis] };
+                pos[u] = if a { maxs[u] } else { mins[u] };
+                pos[v] = if b { maxs[v] } else { mins[v] };
+                Vertex {
+                    pos,
+                    normal,
+                    color,
+                    lightmap,
+                }
+            };
+            let mut quad = [corner(false, false), corner(true, false), corner(true, true), corner(false, true)];
+            if !side {
+                quad.reverse();
+            }
+            builder.polygon(&quad);
+        }
+    }
+}
+
+// `selected` is an entity index, its marker is drawn white and a bit bigger
+pub fn build_markers(markers: &[EntityMarker], selected: Option<usize>, white_uv: [f32; 2]) -> MeshBuilder<Vertex> {
+    let mut builder = MeshBuilder::new();
+    for marker in markers {
+        let is_selected = selected == Some(marker.entity);
+        let color = if is_selected {
+            [1.0, 1.0, 1.0, 1.0]
+        } else {
+            marker.color
+        };
+        let grow = if is_selected { 2.0 } else { 0.0 };
+        let (mins, maxs) = (
+            math::sub(marker.mins, [grow; 3]),
+            math::add(marker.maxs, [grow; 3]),
+        );
+
+        if !marker.brush {
+            cuboid(&mut builder, mins, maxs, color, white_uv);
+            continue;
+        }
+
+        // the 12 edges as thin boxes, a solid box would hide the brushes it belongs to
+        let t = OUTLINE_THICKNESS / 2.0 + grow / 2.0;
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for &a in &[mins[u], maxs[u]] {
+                for &b in &[mins[v], maxs[v]] {
+                    let (mut lo, mut hi) = ([0.0; 3], [0.0; 3]);
+                    lo[axis] = mins[axis] - t;
+                    hi[axis] = maxs[axis] + t;
+                    lo[u] = a - t;
+                    hi[u] = a + t;
+                    lo[v] = b - t;
+                    hi[v] = b + t;
+                    cuboid(&mut builder, lo, hi, color, white_uv);
+                }
+            }
+        }
+    }
+    builder
+}
+
+// Slab test, distance along `dir` to where the ray enters the box
+fn ray_box(origin: Vec3, dir: Vec3, mins: Vec3, maxs: Vec3) -> Option<f32> {
+    let (mut near, mut far) = (0f32, f32::MAX);
+    for i in 0..3 {
+        if dir[i].abs() < 1e-6 {
+            if origin[i] < mins[i] || origin[i] > maxs[i] {
+                return None;
+            }
+            continue;
+        }
+        let (a, b) = ((mins[i] - origin[i]) / dir[i], (maxs[i] - origin[i]) / dir[i]);
+        near = near.max(a.min(b));
+        far = far.min(a.max(b));
+        if near > far {
+            return None;
+        }
+    }
+    Some(near)
+}
+
+// Closest marker along the ray, point entities win over brush bounds the camera is inside of
+pub fn pick_marker(markers: &[EntityMarker], origin: Vec3, dir: Vec3) -> Option<usize> {
+    markers
+        .iter()
+        .filter_map(|marker| {
+            let distance = ray_box(origin, dir, marker.mins, marker.maxs)?;
+            // standing inside a trigger shouldn't make everything else unclickable
+            let inside = distance <= 0.0;
+            Some(((inside, distance), marker.entity))
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|f| f.1)
+}
+
+mod shader {
+    use miniquad as mq;
+
+    pub const VERTEX: &str = r#"#version 100
+attribute vec3 pos;
+attribute vec3 normal;
+attribute vec4 color;
+attribute vec2 lightmap_uv;
+
+uniform mat4 mvp;
+uniform float mode;
+
+varying lowp vec4 v_color;
+varying highp vec2 v_lightmap_uv;
+
+void main() {
+    // fixed "sun" so walls facing different ways are told apart without lightmaps
+    float light = 1.0;
+    if (mode < 0.5) {
+        light = 0.6 + 0.4 * abs(dot(normal, normalize(vec3(0.3, 0.5, 0.8))));
+    }
+    v_color = vec4(color.rgb * light, color.a);
+    if (mode > 2.5 && mode < 3.5) {
+        v_color = vec4(normal * 0.5 + 0.5, 1.0);
+    } else if (mode > 3.5) {
+        v_color = vec4(0.05, 0.05, 0.05, 1.0);
+    }
+    v_lightmap_uv = lightmap_uv;
+    gl_Position = mvp * vec4(pos, 1.0);
+}
+"#;
+
+    pub const FRAGMENT: &str = r#"#version 100
+precision mediump float;
+
+varying lowp vec4 v_color;
+varying highp vec2 v_lightmap_uv;
+
+uniform sampler2D lightmap;
+uniform float mode;
+
+void main() {
+    vec3 light = texture2D(lightmap, v_lightmap_uv).rgb;
+    if (mode > 0.5 && mode < 1.5) {
+        gl_FragColor = vec4(v_color.rgb * light, v_color.a);
+    } else if (mode > 1.5 && mode < 2.5) {
+        gl_FragColor = vec4(light, 1.0);
+    } else {
+        gl_FragColor = v_color;
+    }
+}
+"#;
+
+    pub fn meta() -> mq::ShaderMeta {
+        mq::ShaderMeta {
+            images: vec!["lightmap".to_string()],
+            uniforms: mq::UniformBlockLayout {
+                uniforms: vec![
+                    mq::UniformDesc::new("mvp", mq::UniformType::Mat4),
+                    mq::UniformDesc::new("mode", mq::UniformType::Float1),
+                ],
+            },
+        }
+    }
+
+    // values of the `mode` uniform
+    pub const FLAT: f32 = 0.0;
+    pub const LIGHTMAPPED: f32 = 1.0;
+    pub const LIGHTMAP_ONLY: f32 = 2.0;
+    pub const NORMALS: f32 = 3.0;
+    pub const WIRE: f32 = 4.0; // one dark colour for the lines
+
+    #[repr(C)]
+    pub struct Uniforms {
+        pub mvp: super::Mat4,
+        pub mode: f32,
+    }
+}
+
+pub struct MapRenderer {
+    pub pipeline: mq::Pipeline,
+    pub wire_pipeline: mq::Pipeline,
+    pub overlay_pipeline: mq::Pipeline,
+    pub world: GpuMesh,
+    pub markers: GpuMesh,
+    pub overlay: GpuMesh, // brush volumes, drawn see-through on top
+    pub info_overlays: GpuMesh,
+    pub lights: GpuMesh,
+    pub occluders: GpuMesh, // see-through like the brush overlay
+    pub lightmap: mq::Texture,
+    pub atlas: LightmapAtlas, // pixels are dropped after upload, only the layout is kept for rebuilds
+    pub has_lighting: bool,
+
+    pub mins: Vec3,
+    pub maxs: Vec3,
+}
+
+impl std::fmt::Debug for MapRenderer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MapRenderer")
+            .field("world", &self.world)
+            .field("has_lighting", &self.has_lighting)
+            .field("mins", &self.mins)
+            .field("maxs", &self.maxs)
+            .finish()
+    }
+}
+
+impl MapRenderer {
+    // None if the map has no usable geometry
+    pub fn new(ctx: &mut mq::Context, parsed_map: &ParsedMap) -> Option<Self> {
+        let mut atlas = LightmapAtlas::new(parsed_map);
+        let (builder, mins, maxs) = build_world(parsed_map, &atlas, None, WorldStyle::Shaded);
+        if builder.is_empty() {
+            return None;
+        }
+
+        let lightmap = mq::Texture::from_data_and_format(
+            ctx,
+            &atlas.rgba,
+            mq::TextureParams {
+                width: atlas.width,
+                height: atlas.height,
+                format: mq::TextureFormat::RGBA8,
+                filter: mq::FilterMode::Linear,
+                wrap: mq::TextureWrap::Clamp,
+            },
+        );
+
+        let shader =
+            mq::Shader::new(ctx, shader::VERTEX, shader::FRAGMENT, shader::meta()).unwrap();
+        let attributes = [
+            mq::VertexAttribute::new("pos", mq::VertexFormat::Float3),
+            mq::VertexAttribute::new("normal", mq::VertexFormat::Float3),
+            mq::VertexAttribute::new("color", mq::VertexFormat::Float4),
+            mq::VertexAttribute::new("lightmap_uv", mq::VertexFormat::Float2),
+        ];
+        let pipeline = mq::Pipeline::with_params(
+            ctx,
+            &[mq::BufferLayout::default()],
+            &attributes,
+            shader,
+            mq::PipelineParams {
+                depth_test: mq::Comparison::LessOrEqual,
+                depth_write: true,
+                // pushed back a bit so the wireframe on top doesn't z-fight
+                depth_write_offset: Some((1.0, 1.0)),
+                ..Default::default()
+            },
+        );
+        let wire_pipeline = mq::Pipeline::with_params(
+            ctx,
+            &[mq::BufferLayout::default()],
+            &attributes,
+            shader,
+            mq::PipelineParams {
+                depth_test: mq::Comparison::LessOrEqual,
+                depth_write: false,
+                primitive_type: mq::PrimitiveType::Lines,
+                ..Default::default()
+            },
+        );
+        let overlay_pipeline = mq::Pipeline::with_params(
+            ctx,
+            &[mq::BufferLayout::default()],
+            &attributes,
+            shader,
+            mq::PipelineParams {
+                depth_test: mq::Comparison::LessOrEqual,
+                depth_write: false,
+                color_blend: Some(mq::BlendState::new(
+                    mq::Equation::Add,
+                    mq::BlendFactor::Value(mq::BlendValue::SourceAlpha),
+                    mq::BlendFactor::OneMinusValue(mq::BlendValue::SourceAlpha),
+                )),
+                ..Default::default()
+            },
+        );
+
+        atlas.rgba = Vec::new();
+
+        // the HDR set is only drawn for HDR only maps, both come from the same light entities
+        let lights = if parsed_map.world_lights().is_empty() {
+            parsed_map.world_lights_hdr()
+        } else {
+            parsed_map.world_lights()
+        };
+
+        Some(Self {
+            pipeline,
+            wire_pipeline,
+            overlay_pipeline,
+            world: GpuMesh::new(ctx, &builder, &[lightmap], true),
+            markers: GpuMesh::empty(),
+            overlay: GpuMesh::empty(),
+            info_overlays: GpuMesh::new(ctx, &build_overlays(parsed_map, atlas.white_uv()), &[lightmap], false),
+            lights: GpuMesh::new(ctx, &build_light_gizmos(lights, atlas.white_uv()), &[lightmap], false),
+            occluders: GpuMesh::new(ctx, &build_occluders(parsed_map, atlas.white_uv()), &[lightmap], true),
+            lightmap,
+            has_lighting: atlas.faces.iter().any(|f| f.is_some()),
+            atlas,
+
+            mins,
+            maxs,
+        })
+    }
+
+    pub fn rebuild_world(
+        &mut self,
+        ctx: &mut mq::Context,
+        parsed_map: &ParsedMap,
+        visible: Option<&[bool]>,
+        style: WorldStyle,
+    ) {
+        let (builder, _, _) = build_world(parsed_map, &self.atlas, visible, style);
+        self.world.delete();
+        self.world = GpuMesh::new(ctx, &builder, &[self.lightmap], true);
+    }
+
+    pub fn rebuild_markers(&mut self, ctx: &mut mq::Context, markers: &[EntityMarker], selected: Option<usize>) {
+        let builder = build_markers(markers, selected, self.atlas.white_uv());
+        self.markers.delete();
+        self.markers = GpuMesh::new(ctx, &builder, &[self.lightmap], false);
+    }
+
+    pub fn rebuild_overlay(&mut self, ctx: &mut mq::Context, parsed_map: &ParsedMap, brushes: &[(usize, [f32; 4])]) {
+        let builder = build_brush_overlay(parsed_map, brushes, self.atlas.white_uv());
+        self.overlay.delete();
+        self.overlay = GpuMesh::new(ctx, &builder, &[self.lightmap], true);
+    }
+
+    pub fn draw(&self, ctx: &mut mq::Context, camera: &Camera, aspect: f32, mode: RenderMode, layers: MapLayers) {
+        let mvp = camera.view_proj(aspect);
+        ctx.apply_pipeline(&self.pipeline);
+        ctx.apply_uniforms(&shader::Uniforms {
+            mvp,
+            mode: mode.shader_mode(),
+        });
+        self.world.draw(ctx);
+
+        if layers.markers || layers.lights {
+            // always flat shaded, they'd be plain white in the lightmap only mode
+            ctx.apply_uniforms(&shader::Uniforms {
+                mvp,
+                mode: shader::FLAT,
+            });
+            if layers.markers {
+                self.markers.draw(ctx);
+            }
+            if layers.lights {
+                self.lights.draw(ctx);
+            }
+        }
+
+        if layers.overlays {
+            ctx.apply_pipeline(&self.overlay_pipeline);
+            ctx.apply_uniforms(&shader::Uniforms {
+                mvp,
+                mode: shader::FLAT,
+            });
+            self.info_overlays.draw(ctx);
+        }
+
+        if mode == RenderMode::Wireframe {
+            ctx.apply_pipeline(&self.wire_pipeline);
+            ctx.apply_uniforms(&shader::Uniforms {
+                mvp,
+                mode: shader::WIRE,
+            });
+            self.world.draw_wire(ctx);
+        }
+
+        // last, the blending needs whatever is behind it drawn already
+        if !self.overlay.chunks.is_empty() {
+            ctx.apply_pipeline(&self.wire_pipeline);
+            ctx.apply_uniforms(&shader::Uniforms {
+                mvp,
+                mode: shader::FLAT,
+            });
+            self.overlay.draw_wire(ctx);
+
+            ctx.apply_pipeline(&self.overlay_pipeline);
+            ctx.apply_uniforms(&shader::Uniforms {
+                mvp,
+                mode: shader::FLAT,
+            });
+            self.overlay.draw(ctx);
+        }
+        if layers.occluders && !self.occluders.chunks.is_empty() {
+            ctx.apply_pipeline(&self.wire_pipeline);
+            ctx.apply_uniforms(&shader::Uniforms {
+                mvp,
+                mode: shader::FLAT,
+            });
+            self.occluders.draw_wire(ctx);
+
+            ctx.apply_pipeline(&self.overlay_pipeline);
+            ctx.apply_uniforms(&shader::Uniforms {
+                mvp,
+                mode: shader::FLAT,
+            });
+            self.occluders.draw(ctx);
+        }
+    }
+
+    pub fn delete(&self) {
+        self.world.delete();
+        self.markers.delete();
+        self.overlay.delete();
+        self.info_overlays.delete();
+        self.lights.delete();
+        self.occluders.delete();
+        self.lightmap.delete();
+    }
+}
@@ -4,12 +4,24 @@ use egui::CtxRef;
 use miniquad as mq;
 
 use crate::{
-    blacklist::{Blacklist, BlacklistReason},
-    bsp::BSPLump,
+    blacklist::{
+        file_hash, image_hash, image_hash_string, BlacklistMatch, BlacklistReason, BlacklistSet,
+        DEFAULT_IMAGE_DISTANCE, MAX_IMAGE_DISTANCE,
+    },
+    bsp::{
+        BSPLump, CubemapSample, EmitType, Overlay, WorldLight, CONTENTS_GRATE, CONTENTS_LADDER, CONTENTS_MONSTERCLIP, CONTENTS_PLAYERCLIP,
+        CONTENTS_SLIME, CONTENTS_SOLID, CONTENTS_WATER, CONTENTS_WINDOW, OVERLAY_MAX_FACES,
+        SURF_NODRAW, WATER_OVERLAY_MAX_FACES,
+    },
     kv::{self, Entity},
     lump_helper,
+    map_render::{
+        brush_polygons, entity_markers, pick_marker, sample_ambient, Camera, EntityMarker, MapLayers,
+        MapRenderer, RenderMode, WorldStyle, FLAG_COLOURS,
+    },
+    math,
     platform::{self, file_picker, save_picker},
-    vtf::VTFHEADER,
+    vtf::HeaderProblem,
 };
 
 #[derive(Debug)]
@@ -53,12 +65,66 @@ impl MapWindowOffscreen {
             pass,
         }
     }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.color_img.width, self.color_img.height)
+    }
+
+    pub fn delete(&self, ctx: &mut mq::Context) {
+        self.pass.delete(ctx);
+        self.color_img.delete();
+        self.depth_img.delete();
+    }
 }
 
+// Held movement keys for the noclip camera while the Map view is captured
+#[derive(Debug, Default)]
+pub struct FlyInput {
+    pub forward: bool,
+    pub back: bool,
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub fast: bool,
+    pub slow: bool,
+}
+
+impl FlyInput {
+    // false if the camera doesn't care about the key
+    pub fn set(&mut self, keycode: mq::KeyCode, down: bool) -> bool {
+        match keycode {
+            mq::KeyCode::W => self.forward = down,
+            mq::KeyCode::S => self.back = down,
+            mq::KeyCode::A => self.left = down,
+            mq::KeyCode::D => self.right = down,
+            mq::KeyCode::Space => self.up = down,
+            mq::KeyCode::C => self.down = down,
+            mq::KeyCode::LeftShift | mq::KeyCode::RightShift => self.fast = down,
+            mq::KeyCode::LeftControl | mq::KeyCode::RightControl => self.slow = down,
+            _ => return false,
+        }
+        true
+    }
+}
+
+pub const DEFAULT_FLY_SPEED: f32 = 640.0; // units per second
+const FAST_MULTIPLIER: f32 = 4.0;
+const SLOW_MULTIPLIER: f32 = 0.25;
+
 #[derive(Debug)]
 pub enum TextureProblem {
-    Blacklist(BlacklistReason),
     UnsupportedImageFormat(vtf::ImageFormat), // ?
+    Malformed(Vec<HeaderProblem>),
+}
+
+fn reason_label(v: &BlacklistMatch) -> (egui::color::Color32, String) {
+    let (colour, text) = match &v.reason {
+        BlacklistReason::Game(a) => (egui::color::Color32::RED, format!("Blacklisted game: {}", a)),
+        BlacklistReason::Pack(a) => (egui::color::Color32::GREEN, format!("Pack: {}", a)),
+        BlacklistReason::Custom(_) => (egui::color::Color32::YELLOW, format!("{:?}", v.reason)),
+    };
+    (colour, format!("{} [{}]", text, v.source))
 }
 
 #[derive(Debug)]
@@ -70,58 +136,462 @@ pub struct Texture {
     pub size: u64,
 
     pub problem: Option<TextureProblem>,
+
+    pub pakfile: usize, // index into the PakFile lump
+    pub cubemap: bool,  // uploaded as a cross of all 6 faces
+    pub blacklisted: Vec<BlacklistMatch>,
+    pub image_hash: Option<u64>,
+    pub near_match: Option<(BlacklistMatch, u32)>,
+}
+
+// Material the world geometry refers to through texdata
+#[derive(Debug)]
+pub struct MaterialUsage {
+    pub name: String,
+    pub faces: usize,
+    pub packed: bool, // has a .vmt in the pakfile, otherwise it has to come from the game
+}
+
+fn material_usage(parsed_map: &crate::bsp::ParsedMap) -> Vec<MaterialUsage> {
+    let pak = lump_helper!(&parsed_map.lumps[40], BSPLump::PakFile(v) => v);
+    let file = pak.data(&parsed_map.buf);
+    let packed = pak
+        .files
+        .iter()
+        .map(|f| f.name(file).to_lowercase())
+        .collect::<hashbrown::HashSet<_>>();
+
+    let tex_data = parsed_map.tex_data();
+    let tex_info = parsed_map.tex_info();
+    let mut faces = vec![0usize; tex_data.len()];
+    for face in parsed_map.faces() {
+        if let Some(texinfo) = tex_info.get(face.texinfo as usize) {
+            if let Some(count) = faces.get_mut(texinfo.texdata as usize) {
+                *count += 1;
+            }
+        }
+    }
+
+    // texdata is usually unique per material, but nothing stops a compiler from repeating one
+    let mut materials = hashbrown::HashMap::<String, MaterialUsage>::new();
+    for (texdata, faces) in tex_data.iter().zip(faces) {
+        let name = parsed_map
+            .material_name(texdata)
+            .map(|f| f.replace('\\', "/").to_lowercase())
+            .unwrap_or_else(|| format!("<invalid string table entry {}>", texdata.name_id));
+        let usage = materials.entry(name.clone()).or_insert_with(|| MaterialUsage {
+            packed: packed.contains(&format!("materials/{}.vmt", name)),
+            name,
+            faces: 0,
+        });
+        usage.faces += faces;
+    }
+
+    let mut materials = materials.into_iter().map(|f| f.1).collect::<Vec<_>>();
+    materials.sort_by(|a, b| a.name.cmp(&b.name));
+    materials
+}
+
+// Entry of the static prop model dictionary and where it comes from
+#[derive(Debug)]
+pub struct PropModel {
+    pub name: String,
+    pub props: usize,
+    pub packed: bool,
+    pub provided: Vec<BlacklistMatch>, // loaded lists that ship it, game content mostly
+}
+
+impl PropModel {
+    pub fn missing(&self) -> bool {
+        !self.packed && self.provided.is_empty()
+    }
+}
+
+fn prop_models(parsed_map: &crate::bsp::ParsedMap) -> Vec<PropModel> {
+    let static_props = if let Some(v) = parsed_map.static_props() {
+        v
+    } else {
+        return Vec::new();
+    };
+
+    let pak = lump_helper!(&parsed_map.lumps[40], BSPLump::PakFile(v) => v);
+    let file = pak.data(&parsed_map.buf);
+    let packed = pak
+        .files
+        .iter()
+        .map(|f| f.name(file).to_lowercase())
+        .collect::<hashbrown::HashSet<_>>();
+
+    let mut models = static_props
+        .models
+        .iter()
+        .map(|name| PropModel {
+            packed: packed.contains(&name.replace('\\', "/").to_lowercase()),
+            name: name.clone(),
+            props: 0,
+            provided: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    for prop in &static_props.props {
+        if let Some(model) = models.get_mut(prop.model as usize) {
+            model.props += 1;
+        }
+    }
+    models
+}
+
+// A lump 42 sample and the VTFs buildcubemaps packed for it
+#[derive(Debug)]
+pub struct Cubemap {
+    pub sample: CubemapSample,
+    pub ldr: Option<usize>, // into MapWindowStage.textures
+    pub hdr: Option<usize>,
+}
+
+// "materials/maps/<map>/c<x>_<y>_<z>(.hdr).vtf" -> (map, c<x>_<y>_<z>, hdr)
+fn cubemap_path(name: &str) -> Option<(&str, &str, bool)> {
+    let (map, file) = name.strip_prefix("materials/maps/")?.split_once('/')?;
+    let stem = file.strip_suffix(".vtf")?;
+    let (stem, hdr) = match stem.strip_suffix(".hdr") {
+        Some(v) => (v, true),
+        None => (stem, false),
+    };
+    let coords = stem.strip_prefix('c')?.split('_').collect::<Vec<_>>();
+    if coords.len() != 3 || coords.iter().any(|f| f.parse::<i32>().is_err()) {
+        return None;
+    }
+    Some((map, stem, hdr))
+}
+
+// Samples with whatever got packed for them, plus packed cubemaps nothing uses (texture index, why)
+fn cubemaps(
+    parsed_map: &crate::bsp::ParsedMap,
+    map_name: &str,
+    textures: &[Texture],
+) -> (Vec<Cubemap>, Vec<(usize, String)>) {
+    let map_name = map_name.to_lowercase();
+    let mut packed = hashbrown::HashMap::new();
+    let mut orphans = Vec::new();
+    for (i, texture) in textures.iter().enumerate() {
+        let name = texture.name.replace('\\', "/").to_lowercase();
+        if let Some((map, stem, hdr)) = cubemap_path(&name) {
+            // the engine only looks in the folder named after the map, renaming the .bsp breaks them
+            if map != map_name {
+                orphans.push((i, format!("in maps/{}/ but the map is {}", map, map_name)));
+            } else {
+                packed.insert((stem.to_string(), hdr), i);
+            }
+        }
+    }
+
+    let cubemaps = parsed_map
+        .cubemaps()
+        .iter()
+        .map(|sample| {
+            let stem = sample.file_stem();
+            Cubemap {
+                sample: *sample,
+                ldr: packed.remove(&(stem.clone(), false)),
+                hdr: packed.remove(&(stem, true)),
+            }
+        })
+        .collect();
+    orphans.extend(packed.into_iter().map(|(_, i)| (i, "no cubemap sample at that origin".to_string())));
+    orphans.sort();
+
+    (cubemaps, orphans)
+}
+
+// What a `*N` brush model reference resolves to, for the Entity view
+fn brush_model_label(parsed_map: &crate::bsp::ParsedMap, index: usize) -> (egui::color::Color32, String) {
+    let models = parsed_map.models();
+    let model = if let Some(v) = models.get(index) {
+        v
+    } else {
+        return (
+            egui::color::Color32::RED,
+            format!("model *{} doesn't exist, the map has {}", index, models.len()),
+        );
+    };
+
+    let text = format!(
+        "model *{} | {:.0} {:.0} {:.0} to {:.0} {:.0} {:.0} | {} faces",
+        index,
+        model.mins[0],
+        model.mins[1],
+        model.mins[2],
+        model.maxs[0],
+        model.maxs[1],
+        model.maxs[2],
+        model.num_faces
+    );
+    let faces = parsed_map.faces().len() as i64;
+    if model.first_face < 0 || model.num_faces < 0 || model.first_face as i64 + model.num_faces as i64 > faces {
+        (egui::color::Color32::RED, format!("{} (outside of the {} faces)", text, faces))
+    } else if model.num_faces == 0 {
+        (egui::color::Color32::YELLOW, text)
+    } else {
+        (egui::color::Color32::GRAY, text)
+    }
+}
+
+fn light_label(i: usize, light: &WorldLight) -> String {
+    let [x, y, z] = light.origin;
+    let [r, g, b] = light.intensity;
+    let mut text = format!(
+        "{}: {} | {:.0} {:.0} {:.0} | intensity {:.1} {:.1} {:.1} | cluster {}",
+        i,
+        light.kind.name(),
+        x,
+        y,
+        z,
+        r,
+        g,
+        b,
+        light.cluster
+    );
+    if light.style != 0 {
+        text.push_str(&format!(" | style {}", light.style));
+    }
+    if light.radius > 0.0 {
+        text.push_str(&format!(" | radius {:.0}", light.radius));
+    }
+    text
+}
+
+// What a brush is there for as far as players are concerned, first matching contents wins
+pub const BRUSH_CATEGORIES: [(&str, i32, [f32; 4]); 8] = [
+    ("Player clip", CONTENTS_PLAYERCLIP, [1.0, 0.2, 1.0, 0.3]),
+    ("NPC clip", CONTENTS_MONSTERCLIP, [1.0, 0.3, 0.4, 0.3]),
+    ("Water/slime", CONTENTS_WATER | CONTENTS_SLIME, [0.2, 0.4, 1.0, 0.3]),
+    ("Ladder", CONTENTS_LADDER, [0.8, 0.5, 0.2, 0.3]),
+    ("Grate/window", CONTENTS_GRATE | CONTENTS_WINDOW, [0.2, 1.0, 1.0, 0.3]),
+    // solid, but every side is nodraw, toolsinvisible and friends
+    ("Invisible solid", CONTENTS_SOLID, [1.0, 1.0, 0.2, 0.3]),
+    ("Solid", CONTENTS_SOLID, [0.6, 0.6, 0.6, 0.2]),
+    ("Other", -1, [1.0, 1.0, 1.0, 0.2]),
+];
+const INVISIBLE_SOLID: usize = 5;
+const SOLID: usize = 6;
+const OTHER: usize = 7;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BrushInfo {
+    pub index: usize,
+    pub category: usize, // into BRUSH_CATEGORIES
+    pub contents: i32,
+    pub mins: math::Vec3,
+    pub maxs: math::Vec3,
+}
+
+impl BrushInfo {
+    pub fn volume(&self) -> f32 {
+        let size = math::sub(self.maxs, self.mins);
+        size[0].max(0.0) * size[1].max(0.0) * size[2].max(0.0)
+    }
+}
+
+fn brush_infos(parsed_map: &crate::bsp::ParsedMap) -> Vec<BrushInfo> {
+    let tex_info = parsed_map.tex_info();
+    parsed_map
+        .brushes()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, brush)| {
+            let polygons = brush_polygons(parsed_map, brush);
+            let mut points = polygons.iter().flatten();
+            let first = *points.next()?;
+            let (mins, maxs) = points.fold((first, first), |(mut mins, mut maxs), pos| {
+                for i in 0..3 {
+                    mins[i] = mins[i].min(pos[i]);
+                    maxs[i] = maxs[i].max(pos[i]);
+                }
+                (mins, maxs)
+            });
+
+            let category = if brush.contents & CONTENTS_SOLID != 0 {
+                let invisible = parsed_map
+                    .sides_of(brush)
+                    .iter()
+                    .filter(|f| !f.bevel)
+                    .all(|f| {
+                        tex_info
+                            .get(f.texinfo as usize)
+                            .map(|f| f.flags & SURF_NODRAW != 0)
+                            .unwrap_or(true)
+                    });
+                // clip flags on a solid brush still make it a clip brush
+                BRUSH_CATEGORIES[..INVISIBLE_SOLID]
+                    .iter()
+                    .position(|f| brush.contents & f.1 != 0)
+                    .unwrap_or(if invisible { INVISIBLE_SOLID } else { SOLID })
+            } else {
+                BRUSH_CATEGORIES[..INVISIBLE_SOLID]
+                    .iter()
+                    .position(|f| brush.contents & f.1 != 0)
+                    .unwrap_or(OTHER)
+            };
+
+            Some(BrushInfo {
+                index,
+                category,
+                contents: brush.contents,
+                mins,
+                maxs,
+            })
+        })
+        .collect()
+}
+
+// Ambient cube under the camera as six swatches, or why there isn't one
+fn ambient_ui(ui: &mut egui::Ui, parsed_map: &crate::bsp::ParsedMap, leaf: Option<usize>, pos: math::Vec3) {
+    const SIDES: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+    let (indices, samples) = parsed_map.ambient();
+    if samples.is_empty() {
+        // v19 keeps the cubes in the leafs themselves, only newer maps can be missing them
+        if parsed_map.version >= 20 {
+            ui.colored_label(
+                egui::color::Color32::RED,
+                "No ambient lighting, props without baked lighting will render black",
+            );
+        }
+        return;
+    }
+    if indices.len() != parsed_map.leafs().len() {
+        ui.colored_label(
+            egui::color::Color32::YELLOW,
+            format!("{} ambient indices for {} leafs", indices.len(), parsed_map.leafs().len()),
+        );
+    }
+
+    let leaf = match leaf {
+        Some(v) if parsed_map.leafs().get(v).map(|f| f.cluster >= 0) == Some(true) => v,
+        _ => return,
+    };
+    match sample_ambient(parsed_map, leaf, pos) {
+        Some(cube) => {
+            ui.horizontal(|ui| {
+                ui.label(format!("ambient ({} samples)", parsed_map.leaf_ambient(leaf).len()));
+                for (name, [r, g, b]) in SIDES.iter().zip(cube.iter()) {
+                    let (rect, response) = ui.allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::hover());
+                    ui.painter().rect_filled(rect, 2.0, egui::color::Color32::from_rgb(*r, *g, *b));
+                    response.on_hover_text(format!("{}: {} {} {}", name, r, g, b));
+                }
+            });
+        }
+        None => {
+            ui.colored_label(
+                egui::color::Color32::YELLOW,
+                "No ambient samples in this leaf, props here will render black",
+            );
+        }
+    }
+}
+
+// Which of the extra per-map windows are open, all of them are off by default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MapViews {
+    pub materials: bool,
+    pub props: bool,
+    pub displacements: bool,
+    pub brushes: bool,
+    pub cubemaps: bool,
+    pub overlays: bool,
+    pub lights: bool,
+    pub physics: bool,
 }
 
 #[derive(Debug)]
 pub struct MapWindowStage {
     pub name: String,
     pub offscreen: MapWindowOffscreen,
+    pub view_size: (u32, u32), // what the Map view wants the offscreen target to be
+    pub renderer: Option<MapRenderer>,
+    pub camera: Camera,
+    pub fly: FlyInput,
+    pub fly_speed: f32,
+    pub render_mode: RenderMode,
+    pub show_pvs: bool,
+    pub pvs_cluster: Option<i16>, // what the world mesh is currently dimmed for
+    pub world_style: WorldStyle,  // and what it was built for
+    pub camera_leaf: Option<usize>,
+    pub vis_coverage: Option<(f32, usize)>, // average coverage, clusters with a broken PVS
+    pub markers: Vec<EntityMarker>,
+    pub layers: MapLayers,
+    pub marker_selection: Option<usize>, // what the marker mesh was built for, None rebuilds it
+    pub brushes: Vec<BrushInfo>,
+    pub brush_overlay: [bool; BRUSH_CATEGORIES.len()],
+    pub overlay_built: [bool; BRUSH_CATEGORIES.len()],
 
     pub parsed_map: Option<crate::bsp::ParsedMap>,
     pub textures: Vec<Texture>,
 
     pub entities: Vec<Entity>, // TODO: allow for proper K-V editor
     pub current_entity: usize,
+    pub materials: Vec<MaterialUsage>,
+    pub prop_models: Vec<PropModel>,
+    pub cubemaps: Vec<Cubemap>,
+    pub orphaned_cubemaps: Vec<(usize, String)>,
 
     pub open: bool,
+    pub views: MapViews,
+    // LZMA the entity lump on save, starts as whatever the map had. Edits that grew it can fit again compressed.
+    pub compress_entities: bool,
+    pub lump_problems: Vec<String>, // .lmp files that failed to apply or don't match the map
+    pub save_error: Option<String>,  // why the last save or export from the menu didn't happen
 
     pub blacklisted_texture: bool,
     pub blacklisted_file: bool,
+    pub image_distance: u32,
 
     pub file_filter: String,
     pub texture_filter: String,
     pub entity_filter: String,
+    pub material_filter: String,
+    pub unpacked_materials: bool,
+    pub prop_filter: String,
+    pub missing_props: bool,
 
     pub new_view: bool, // everything in one list
 }
 
 impl MapWindowStage {
+    // `lumps` are .lmp overrides as (file name, data), applied in order before anything else looks at the map
     pub fn new(
         name: String,
         buf: Vec<u8>,
+        lumps: &[(String, Vec<u8>)],
         ctx: &mut mq::Context,
         width: u32,
         height: u32,
-        blacklist: Option<&Blacklist>,
+        blacklists: &BlacklistSet,
     ) -> Result<Self, Box<dyn Error>> {
         let mut parsed_map = crate::bsp::ParsedMap::new(buf)?;
-
-        let pak = lump_helper!(&mut parsed_map.lumps[40], crate::bsp::BSPLump::PakFile(v) => v);
-        let file =
-            &parsed_map.buf[pak.base.offset as usize..(pak.base.offset + pak.base.size) as usize];
-
-        if let Some(blacklist) = blacklist {
-            for i in &mut pak.files {
-                if let Some(v) = blacklist.check(i.data(file)) {
-                    i.blacklisted = Some(v);
+        // a broken override shouldn't keep the map from opening, the engine skips those too
+        let mut lump_problems = Vec::new();
+        for (file_name, data) in lumps {
+            match parsed_map.apply_lmp(data) {
+                Ok(header) if header.revision as u32 != parsed_map.iteration => {
+                    lump_problems.push(format!(
+                        "{} is for map revision {}, the map is at {}",
+                        file_name, header.revision, parsed_map.iteration
+                    ));
                 }
+                Ok(_) => {}
+                Err(v) => lump_problems.push(format!("{}: {}", file_name, v)),
             }
         }
 
+        let pak = lump_helper!(&parsed_map.lumps[40], crate::bsp::BSPLump::PakFile(v) => v);
+        let file =
+            &parsed_map.buf[pak.base.offset as usize..(pak.base.offset + pak.base.size) as usize];
+
         let textures = pak
             .files
             .iter()
-            .filter(|pakfile| {
+            .enumerate()
+            .filter(|(_, pakfile)| {
                 let name = unsafe {
                     std::str::from_utf8_unchecked(
                         &file[pakfile.name.0 as usize..(pakfile.name.0 + pakfile.name.1) as usize],
@@ -129,122 +599,93 @@ impl MapWindowStage {
                 };
                 name.ends_with(".vtf")
             })
-            .map(|pakfile| {
+            .map(|(index, pakfile)| {
                 let file_data = pakfile.data(file);
-                // this library sucks... I needed to fork it just to make it work properly cuz no one tests when accepts PRs...
-                let mut data_vec = file_data.to_vec();
-                let vtf = vtf::from_bytes(&mut data_vec).unwrap();
-                let image = vtf.highres_image;
-
                 let name = pakfile.name(file);
-
-                #[cfg(debug_assertions)]
-                println!(
-                    "{} | {:#?} | {}x{}",
-                    name, image.format, image.width, image.height
-                );
-
                 let size = pakfile.data.1 as u64 + pakfile.name.1 as u64 + 30;
 
-                // This library is so bad: UnsupportedImageFormat(Rgba16161616f)
-                if let Ok(decoded) = image.decode(0) {
-                    Texture {
-                        texture: mq::Texture::from_rgba8(
-                            ctx,
-                            image.width,
-                            image.height,
-                            decoded.into_rgba8().to_vec().as_slice(),
-                        ),
-                        name: name.to_string(),
-                        to_remove: false,
-
-                        problem: if let Some(reason) = &pakfile.blacklisted {
-                            Some(TextureProblem::Blacklist(reason.clone()))
-                        } else {
+                let mut problems = crate::vtf::check_header(file_data);
+                let decoded = if problems.iter().any(|f| f.is_fatal()) {
+                    None
+                } else {
+                    // decode_rgba8 only hands checked headers to the vtf crate, this is a backstop for what's left of
+                    // its panics. Native only, wasm builds abort on panic so there it catches nothing.
+                    match std::panic::catch_unwind(|| crate::vtf::decode_rgba8(file_data)) {
+                        Ok(Ok(v)) => Some(Ok(v)),
+                        Ok(Err(crate::vtf::DecodeError::Malformed(v))) => {
+                            problems.push(v);
                             None
-                        },
-
-                        size,
+                        }
+                        Ok(Err(crate::vtf::DecodeError::UnsupportedImageFormat(v))) => {
+                            Some(Err(v))
+                        }
+                        Err(_) => {
+                            problems.push(HeaderProblem::Decode("panicked".to_string()));
+                            None
+                        }
                     }
-                } else {
-                    match image.format {
-                        vtf::ImageFormat::Rgba16161616f => {
-                            let image_data = image.get_frame(0).unwrap();
-                            // RGBA8
-                            let mut bytes = vec![0u8; image_data.len() / 2];
-                            for i in 0..(image_data.len() / 2) {
-                                let slice = &image_data[i * 2..2 + (i * 2)];
-                                let array = [slice[0], slice[1]];
-                                let chan = half::f16::from_le_bytes(array);
-                                // println!("{}", chan);
-                                bytes[i] = (chan.to_f32().max(0.0).min(1.0) * 255.0) as u8;
-                            }
-                            Texture {
-                                texture: mq::Texture::from_rgba8(
-                                    ctx,
-                                    image.width,
-                                    image.height,
-                                    &bytes,
-                                ),
-                                name: name.to_string(),
-                                to_remove: false,
+                };
 
-                                problem: if let Some(reason) = &pakfile.blacklisted {
-                                    Some(TextureProblem::Blacklist(reason.clone()))
-                                } else {
-                                    None
-                                },
+                #[cfg(debug_assertions)]
+                if let Some(Ok(image)) = &decoded {
+                    println!("{} | {}x{}", name, image.width, image.height);
+                }
 
-                                size,
-                            }
-                        }
-                        vtf::ImageFormat::Abgr8888 => {
-                            let mut image_data = image.get_frame(0).unwrap().to_vec();
-                            for idx in 0..image_data.len() / 4 {
-                                let i = idx * 4;
-                                if let [a, b, c, d] = image_data[i..i + 4] {
-                                    image_data[i..i + 4].copy_from_slice(&[d, c, b, a]);
-                                } else {
-                                    unreachable!()
-                                }
-                            }
-                            Texture {
-                                texture: mq::Texture::from_rgba8(
-                                    ctx,
-                                    image.width,
-                                    image.height,
-                                    &image_data,
-                                ),
-                                name: name.to_string(),
-                                to_remove: false,
+                let image_hash = if let Some(Ok(image)) = &decoded {
+                    image_hash(&image.rgba, image.width as u32, image.height as u32)
+                } else {
+                    None
+                };
+                // cubemaps show all their faces, the hash above stays on the first one like blacklist_gen's
+                let mut cubemap = false;
+                let decoded = match decoded {
+                    Some(Ok(image)) if crate::vtf::is_envmap(file_data) => {
+                        let cross = std::panic::catch_unwind(|| crate::vtf::decode_faces(file_data))
+                            .ok()
+                            .and_then(|f| f.ok())
+                            .and_then(|f| crate::vtf::cubemap_cross(&f));
+                        cubemap = cross.is_some();
+                        Some(Ok(cross.unwrap_or(image)))
+                    }
+                    v => v,
+                };
+                let (texture, problem) = match decoded {
+                    Some(Ok(image)) => (
+                        mq::Texture::from_rgba8(ctx, image.width, image.height, &image.rgba),
+                        None,
+                    ),
+                    Some(Err(format)) => {
+                        // TODO...
+                        eprintln!("Failed... {} {:?}", name, format);
+                        (
+                            unsafe { mq::Texture::from_raw_id(0) },
+                            Some(TextureProblem::UnsupportedImageFormat(format)),
+                        )
+                    }
+                    None => {
+                        eprintln!("Malformed... {} {:?}", name, problems);
+                        (unsafe { mq::Texture::from_raw_id(0) }, None)
+                    }
+                };
 
-                                problem: if let Some(reason) = &pakfile.blacklisted {
-                                    Some(TextureProblem::Blacklist(reason.clone()))
-                                } else {
-                                    None
-                                },
+                Texture {
+                    texture,
+                    name: name.to_string(),
+                    to_remove: false,
 
-                                size,
-                            }
-                        }
-                        _ => {
-                            // TODO...
-                            eprintln!("Failed... {}x{}", image.width, image.height);
-                            Texture {
-                                texture: unsafe { mq::Texture::from_raw_id(0) },
-                                name: name.to_string(),
-                                to_remove: false,
-
-                                problem: if let Some(reason) = &pakfile.blacklisted {
-                                    Some(TextureProblem::Blacklist(reason.clone()))
-                                } else {
-                                    Some(TextureProblem::UnsupportedImageFormat(image.format))
-                                },
+                    problem: if !problems.is_empty() {
+                        Some(TextureProblem::Malformed(problems))
+                    } else {
+                        problem
+                    },
 
-                                size,
-                            }
-                        }
-                    }
+                    pakfile: index,
+                    cubemap,
+                    blacklisted: Vec::new(),
+                    image_hash,
+                    near_match: None,
+
+                    size,
                 }
             })
             .collect::<Vec<_>>();
@@ -255,27 +696,152 @@ impl MapWindowStage {
                 .as_str(),
         );
 
-        Ok(Self {
+        let markers = entity_markers(&parsed_map, &entities);
+        let materials = material_usage(&parsed_map);
+        let vis_coverage = parsed_map.vis_coverage();
+        let compress_entities = parsed_map.compressed[0];
+        let prop_models = prop_models(&parsed_map);
+        let brushes = brush_infos(&parsed_map);
+        let (cubemaps, orphaned_cubemaps) = cubemaps(&parsed_map, &name, &textures);
+
+        let renderer = MapRenderer::new(ctx, &parsed_map);
+        let camera = if let Some(renderer) = &renderer {
+            Camera::framing(renderer.mins, renderer.maxs)
+        } else {
+            Camera::framing([0.0; 3], [0.0; 3])
+        };
+
+        let mut ret = Self {
             name,
             offscreen: MapWindowOffscreen::new(ctx, width, height),
+            view_size: (width, height),
+            renderer,
+            camera,
+            fly: Default::default(),
+            fly_speed: DEFAULT_FLY_SPEED,
+            render_mode: RenderMode::Flat,
+            show_pvs: false,
+            pvs_cluster: None,
+            world_style: WorldStyle::Shaded,
+            camera_leaf: None,
+            vis_coverage,
+            markers,
+            layers: MapLayers {
+                markers: true,
+                overlays: true,
+                lights: false,
+                occluders: true,
+            },
+            marker_selection: None,
+            brushes,
+            brush_overlay: [false; BRUSH_CATEGORIES.len()],
+            overlay_built: [false; BRUSH_CATEGORIES.len()],
 
             parsed_map: Some(parsed_map),
             textures,
 
             entities,
             current_entity: 0,
+            materials,
+            prop_models,
+            cubemaps,
+            orphaned_cubemaps,
 
             open: true,
+            views: Default::default(),
+            compress_entities,
+            lump_problems,
+            save_error: None,
 
             blacklisted_texture: false,
             blacklisted_file: false,
+            image_distance: DEFAULT_IMAGE_DISTANCE,
 
             file_filter: "".to_string(),
             texture_filter: "".to_string(),
             entity_filter: "".to_string(),
+            material_filter: "".to_string(),
+            unpacked_materials: false,
+            prop_filter: "".to_string(),
+            missing_props: false,
 
             new_view: false,
-        })
+        };
+        ret.recheck(blacklists);
+
+        Ok(ret)
+    }
+
+    // Blacklists can come and go while the map is open
+    pub fn recheck(&mut self, blacklists: &BlacklistSet) {
+        if let Some(parsed_map) = self.parsed_map.as_mut() {
+            let pak = lump_helper!(&mut parsed_map.lumps[40], BSPLump::PakFile(v) => v);
+            let file = &parsed_map.buf
+                [pak.base.offset as usize..(pak.base.offset + pak.base.size) as usize];
+
+            for i in &mut pak.files {
+                let sha = match i.sha {
+                    Some(v) => v,
+                    None => file_hash(i.data(file)),
+                };
+                i.sha = Some(sha);
+                i.blacklisted = blacklists.check(&sha);
+            }
+
+            for texture in &mut self.textures {
+                texture.blacklisted = pak.files[texture.pakfile].blacklisted.clone();
+                // exact matches are already reported by the SHA check
+                texture.near_match = match texture.image_hash {
+                    Some(hash) if texture.blacklisted.is_empty() => blacklists.check_image(hash),
+                    _ => None,
+                };
+            }
+        }
+
+        for model in &mut self.prop_models {
+            model.provided = blacklists.check_path(&model.name);
+        }
+    }
+
+    // Called every frame the Map view is captured
+    pub fn fly(&mut self, dt: f32) {
+        let fly = &self.fly;
+        let axis = |pos: bool, neg: bool| pos as i32 as f32 - neg as i32 as f32;
+
+        let forward = self.camera.forward();
+        let right = self.camera.right();
+        let dir = math::add(
+            math::add(
+                math::scale(forward, axis(fly.forward, fly.back)),
+                math::scale(right, axis(fly.right, fly.left)),
+            ),
+            [0.0, 0.0, axis(fly.up, fly.down)],
+        );
+
+        let mut speed = self.fly_speed;
+        if fly.fast {
+            speed *= FAST_MULTIPLIER;
+        }
+        if fly.slow {
+            speed *= SLOW_MULTIPLIER;
+        }
+
+        self.camera.pos = math::add(self.camera.pos, math::scale(math::normalize(dir), speed * dt));
+    }
+
+    // After anything touched the entity list, origins and models might have moved
+    pub fn update_markers(&mut self) {
+        if let Some(parsed_map) = &self.parsed_map {
+            self.markers = entity_markers(parsed_map, &self.entities);
+        }
+        self.marker_selection = None;
+    }
+
+    // Selects whatever entity is under the crosshair, the middle of the view
+    pub fn pick_entity(&mut self) {
+        if let Some(entity) = pick_marker(&self.markers, self.camera.pos, self.camera.forward()) {
+            self.current_entity = entity;
+        }
     }
 
     pub fn render_map(&mut self, ctx: &mut mq::Context) {
@@ -283,14 +849,77 @@ impl MapWindowStage {
             return;
         }*/
 
-        let Self { parsed_map, .. } = self;
+        let Self {
+            renderer,
+            camera,
+            offscreen,
+            view_size,
+            render_mode,
+            parsed_map,
+            show_pvs,
+            pvs_cluster,
+            world_style,
+            camera_leaf,
+            markers,
+            layers,
+            marker_selection,
+            current_entity,
+            brushes,
+            brush_overlay,
+            overlay_built,
+            ..
+        } = self;
+
+        if let (Some(renderer), Some(parsed_map)) = (renderer.as_mut(), parsed_map.as_ref()) {
+            *camera_leaf = parsed_map.find_leaf(camera.pos);
+            let cluster = camera_leaf
+                .and_then(|f| parsed_map.leafs().get(f))
+                .map(|f| f.cluster)
+                .filter(|_| *show_pvs);
+            let style = render_mode.world_style();
+            // only rebuild when the camera crosses into another cluster or the mode needs other faces
+            if cluster != *pvs_cluster || style != *world_style {
+                let visible = cluster
+                    .filter(|f| *f >= 0)
+                    .and_then(|f| parsed_map.visible_faces(f as usize));
+                renderer.rebuild_world(ctx, parsed_map, visible.as_deref(), style);
+                *pvs_cluster = cluster;
+                *world_style = style;
+            }
+        }
+
+        if let (Some(renderer), Some(parsed_map)) = (renderer.as_mut(), parsed_map.as_ref()) {
+            if brush_overlay != overlay_built {
+                let shown = brushes
+                    .iter()
+                    .filter(|f| brush_overlay[f.category])
+                    .map(|f| (f.index, BRUSH_CATEGORIES[f.category].2))
+                    .collect::<Vec<_>>();
+                renderer.rebuild_overlay(ctx, parsed_map, &shown);
+                *overlay_built = *brush_overlay;
+            }
+        }
+
+        if let Some(renderer) = renderer.as_mut() {
+            if *marker_selection != Some(*current_entity) {
+                renderer.rebuild_markers(ctx, markers, Some(*current_entity));
+                *marker_selection = Some(*current_entity);
+            }
+        }
+
+        // the texture id egui got this frame is stale for a frame, not worth working around
+        if offscreen.size() != *view_size {
+            offscreen.delete(ctx);
+            *offscreen = MapWindowOffscreen::new(ctx, view_size.0, view_size.1);
+        }
 
         ctx.begin_pass(
-            self.offscreen.pass,
-            mq::PassAction::clear_color(1.0, 1.0, 1.0, 1.),
+            offscreen.pass,
+            mq::PassAction::clear_color(0.2, 0.2, 0.25, 1.),
         );
-        if let Some(parsed_map) = parsed_map {
-            // TODO: render map some time soon?
+        if let Some(renderer) = renderer {
+            let aspect = offscreen.color_img.width as f32 / offscreen.color_img.height.max(1) as f32;
+            renderer.draw(ctx, camera, aspect, *render_mode, *layers);
         }
         ctx.end_render_pass()
     }
@@ -302,19 +931,166 @@ impl MapWindowStage {
             // TODO: split logic at least into multiple different functions...
 
             let offscreen = egui::TextureId::User(self.offscreen.col_id as u64);
+            let parsed_map = &self.parsed_map;
+            let view_size = &mut self.view_size;
+            let fly_speed = self.fly_speed;
+            let render_mode = &mut self.render_mode;
+            let has_lighting = self.renderer.as_ref().map(|f| f.has_lighting);
+            let show_pvs = &mut self.show_pvs;
+            let camera_leaf = self.camera_leaf;
+            let camera_pos = self.camera.pos;
+            let lump_problems = &self.lump_problems;
+            let save_error = &self.save_error;
+            let vis_coverage = self.vis_coverage;
+            let layers = &mut self.layers;
             egui::Window::new(format!("[{}] Map view", self.name))
                 .resizable(true)
                 .collapsible(true)
                 .open(&mut self.open)
-                .default_width(256.0)
+                .default_size([256.0, 280.0])
                 .show(egui_ctx, |ui| {
-                    if ui
-                        .add(egui::ImageButton::new(offscreen, [256.0; 2]))
+                    // leave room for the stats and mode selector below
+                    let size = ui.available_size() - egui::vec2(0.0, 140.0);
+                    let size = egui::vec2(size.x.max(64.0), size.y.max(64.0));
+                    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+                    egui::Image::new(offscreen, size)
+                        // render targets come out upside down
+                        .uv(egui::Rect::from_min_max(
+                            egui::pos2(0.0, 1.0),
+                            egui::pos2(1.0, 0.0),
+                        ))
+                        .paint_at(ui, rect);
+                    // picking goes by the middle of the view
+                    let center = rect.center();
+                    let stroke = (1.0, egui::color::Color32::WHITE);
+                    ui.painter().line_segment([center - egui::vec2(6.0, 0.0), center + egui::vec2(6.0, 0.0)], stroke);
+                    ui.painter().line_segment([center - egui::vec2(0.0, 6.0), center + egui::vec2(0.0, 6.0)], stroke);
+                    if response
+                        .on_hover_text("Click to fly: WASD, Space/C up/down, Shift/Ctrl faster/slower, scroll changes speed, click selects the entity under the crosshair, Escape to release")
                         .clicked()
                     {
                         grabbed = true;
                     }
 
+                    let pixels_per_point = egui_ctx.pixels_per_point();
+                    *view_size = (
+                        (size.x * pixels_per_point) as u32,
+                        (size.y * pixels_per_point) as u32,
+                    );
+
+                    if let Some(parsed_map) = parsed_map {
+                        ui.label(format!(
+                            "{} faces | {} vertices | {} planes | v{} | {} u/s",
+                            parsed_map.faces().len(),
+                            parsed_map.vertices().len(),
+                            parsed_map.planes().len(),
+                            parsed_map.version,
+                            fly_speed,
+                        ));
+                        let compressed = parsed_map.compressed.iter().filter(|f| **f).count();
+                        if compressed > 0 {
+                            ui.label(format!("{} LZMA compressed lumps", compressed));
+                        }
+                        if !parsed_map.overrides.is_empty() {
+                            let ids = parsed_map
+                                .overrides
+                                .iter()
+                                .map(|f| f.id.to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ");
+                            ui.label(format!("Lumps overridden by .lmp files: {}", ids));
+                        }
+                    }
+                    for problem in lump_problems {
+                        ui.colored_label(egui::color::Color32::YELLOW, problem);
+                    }
+                    if let Some(v) = save_error {
+                        ui.colored_label(egui::color::Color32::RED, v);
+                    }
+
+                    ui.horizontal_wrapped(|ui| {
+                        for mode in &RenderMode::ALL {
+                            ui.radio_value(render_mode, *mode, mode.name());
+                        }
+                    });
+                    if *render_mode == RenderMode::Flags {
+                        ui.horizontal_wrapped(|ui| {
+                            for (name, _, [r, g, b, _]) in &FLAG_COLOURS {
+                                let colour = egui::color::Color32::from_rgb(
+                                    (r * 255.0) as u8,
+                                    (g * 255.0) as u8,
+                                    (b * 255.0) as u8,
+                                );
+                                ui.colored_label(colour, name);
+                            }
+                        });
+                    }
+                    if has_lighting == Some(false) {
+                        ui.colored_label(
+                            egui::color::Color32::YELLOW,
+                            "No lightmaps, the map was probably compiled without VRAD",
+                        );
+                    }
+
+                    if let Some(parsed_map) = parsed_map {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut layers.markers, "Entities");
+                            ui.checkbox(&mut layers.overlays, "Overlays");
+                            ui.checkbox(&mut layers.lights, "Lights");
+                            ui.checkbox(&mut layers.occluders, "Occluders");
+                            ui.checkbox(show_pvs, "Show PVS");
+                            let leaf = camera_leaf.and_then(|f| Some((f, parsed_map.leafs().get(f)?)));
+                            match (leaf, parsed_map.visibility()) {
+                                (Some((i, leaf)), Some(vis)) if leaf.cluster >= 0 => {
+                                    let visible = vis
+                                        .pvs(leaf.cluster as usize)
+                                        .map(|f| f.iter().map(|f| f.count_ones()).sum::<u32>())
+                                        .unwrap_or(0);
+                                    ui.label(format!(
+                                        "leaf {} | cluster {} | {}/{} clusters visible",
+                                        i, leaf.cluster, visible, vis.num_clusters
+                                    ));
+                                }
+                                (Some((i, leaf)), _) if leaf.cluster < 0 => {
+                                    ui.label(format!("leaf {} | outside the world", i));
+                                }
+                                (Some((i, leaf)), _) => {
+                                    ui.label(format!("leaf {} | cluster {}", i, leaf.cluster));
+                                }
+                                _ => {}
+                            }
+                        });
+                        match vis_coverage {
+                            None => {
+                                ui.colored_label(
+                                    egui::color::Color32::RED,
+                                    "No visibility data, the map was compiled without VVIS",
+                                );
+                            }
+                            Some((v, broken)) => {
+                                if broken > 0 {
+                                    ui.colored_label(
+                                        egui::color::Color32::RED,
+                                        format!("{} clusters have broken visibility data", broken),
+                                    );
+                                }
+                                // a single cluster always sees everything, nothing to warn about
+                                let clusters = parsed_map.visibility().map_or(0, |f| f.num_clusters);
+                                if v > 0.9 && clusters > 1 && broken < clusters {
+                                    ui.colored_label(
+                                        egui::color::Color32::YELLOW,
+                                        format!(
+                                            "Clusters see {:.0}% of the map on average, visibility is barely culling anything",
+                                            v * 100.0
+                                        ),
+                                    );
+                                }
+                            }
+                        }
+
+                        ambient_ui(ui, parsed_map, camera_leaf, camera_pos);
+                    }
+
                     ui.allocate_space(egui::vec2(0.0, 0.0));
                 });
 
@@ -324,6 +1100,12 @@ impl MapWindowStage {
                 .default_width(712.0)
                 .show(egui_ctx, |ui| {
                     ui.checkbox(&mut self.blacklisted_texture, "Show only blacklisted");
+                    let image_distance = &mut self.image_distance;
+                    ui.add(
+                        egui::Slider::new(image_distance, 0..=MAX_IMAGE_DISTANCE)
+                            .text("Near-match distance"),
+                    );
+                    let image_distance = *image_distance;
                     let filter = &mut self.texture_filter;
                     ui.horizontal(|ui| {
                         ui.label("Search");
@@ -352,8 +1134,16 @@ impl MapWindowStage {
                         //     egui::TextureId::User(texture.gl_internal_id() as u64),
                         //     [256.0; 2],
                         // ));
+                        let near_match = texture
+                            .near_match
+                            .as_ref()
+                            .filter(|(_, distance)| *distance <= image_distance)
+                            .cloned();
                         if self.blacklisted_texture {
-                            if texture.problem.is_none() {
+                            if texture.problem.is_none()
+                                && texture.blacklisted.is_empty()
+                                && near_match.is_none()
+                            {
                                 continue;
                             }
                         }
@@ -373,38 +1163,57 @@ impl MapWindowStage {
                             );
                             ui.vertical(|ui| {
                                 ui.label(&texture.name);
-                                ui.label(format!(
-                                    "{}x{} | {}",
-                                    texture.texture.width, texture.texture.height,
-                                    bytesize::to_string(texture.size, false)
-                                ));
+                                if texture.cubemap {
+                                    ui.label(format!(
+                                        "cubemap {}x{} per face | {}",
+                                        texture.texture.width / 4, texture.texture.height / 3,
+                                        bytesize::to_string(texture.size, false)
+                                    ));
+                                } else {
+                                    ui.label(format!(
+                                        "{}x{} | {}",
+                                        texture.texture.width, texture.texture.height,
+                                        bytesize::to_string(texture.size, false)
+                                    ));
+                                }
+                                for reason in &texture.blacklisted {
+                                    let (colour, text) = reason_label(reason);
+                                    ui.colored_label(colour, text);
+                                }
                                 if let Some(problem) = &texture.problem {
                                     // what the fuck did I do here
-                                    let (colour, text) = if let TextureProblem::Blacklist(
-                                        BlacklistReason::Game(a),
-                                    ) = &problem
+                                    let (colour, text) = if let TextureProblem::UnsupportedImageFormat(f) =
+                                        &problem
                                     {
                                         (
                                             egui::color::Color32::RED,
-                                            format!("Blacklisted game: {}", &a),
+                                            format!("Unsupported image format: {:?}", f),
                                         )
-                                    } else if let TextureProblem::Blacklist(
-                                        BlacklistReason::Pack(a),
-                                    ) = &problem
-                                    {
-                                        (egui::color::Color32::GREEN, format!("Pack: {}", &a))
-                                    } else if let TextureProblem::UnsupportedImageFormat(f) =
-                                        &problem
-                                    {
+                                    } else if let TextureProblem::Malformed(v) = &problem {
                                         (
                                             egui::color::Color32::RED,
-                                            format!("Unsupported image format: {:?}", f),
+                                            v.iter()
+                                                .map(|f| format!("Malformed VTF: {}", f))
+                                                .collect::<Vec<_>>()
+                                                .join("\n"),
                                         )
                                     } else {
                                         (egui::color::Color32::YELLOW, format!("{:?}", &problem))
                                     };
                                     ui.colored_label(colour, text);
                                 }
+                                if let Some((v, distance)) = &near_match {
+                                    ui.colored_label(
+                                        egui::color::Color32::YELLOW,
+                                        format!(
+                                            "Looks like: {:?} [{}] (distance {})",
+                                            v.reason, v.source, distance
+                                        ),
+                                    );
+                                }
+                                if let Some(hash) = texture.image_hash {
+                                    ui.label(image_hash_string(hash));
+                                }
                                 ui.checkbox(&mut texture.to_remove, "Remove");
                             });
                         });
@@ -428,18 +1237,14 @@ impl MapWindowStage {
                         if ui.button("Export Used List").clicked() {
                             let mut hesh = hashbrown::HashSet::new();
                             for pakfile in &paklump.files {
-                                if let Some(blacklist) = &pakfile.blacklisted {
-                                    hesh.insert(match blacklist {
-                                        BlacklistReason::Game(a) => a,
-                                        BlacklistReason::Pack(a) => a,
-                                        BlacklistReason::Custom(a) => a,
-                                    });
+                                for blacklist in &pakfile.blacklisted {
+                                    hesh.insert(blacklist.reason.name());
                                 }
                             }
                             let k0k = {
                                 let mut brih = String::with_capacity(hesh.len() * 50);
                                 for i in hesh {
-                                    brih.push_str(i.as_str());
+                                    brih.push_str(i);
                                     brih.push('\n');
                                 }
                                 brih
@@ -474,7 +1279,7 @@ impl MapWindowStage {
 
                         for pakfile in &mut paklump.files {
                             if *blacklisted_file_mut {
-                                if pakfile.blacklisted.is_none() {
+                                if pakfile.blacklisted.is_empty() {
                                     continue;
                                 }
                             }
@@ -488,18 +1293,8 @@ impl MapWindowStage {
                                 ui.checkbox(&mut pakfile.remove, name);
                                 let size = pakfile.data.1 as u64 + pakfile.name.1 as u64 + 30;
                                 ui.label(bytesize::to_string(size, false));
-                                if let Some(v) = &pakfile.blacklisted {
-                                    // what the fuck did I do here
-                                    let (colour, text) = if let BlacklistReason::Game(a) = &v {
-                                        (
-                                            egui::color::Color32::RED,
-                                            format!("Blacklisted game: {}", &a),
-                                        )
-                                    } else if let BlacklistReason::Pack(a) = &v {
-                                        (egui::color::Color32::GREEN, format!("Pack: {}", &a))
-                                    } else {
-                                        (egui::color::Color32::YELLOW, format!("{:?}", &v))
-                                    };
+                                for v in &pakfile.blacklisted {
+                                    let (colour, text) = reason_label(v);
                                     ui.colored_label(colour, text);
                                 }
                             });
@@ -512,6 +1307,11 @@ impl MapWindowStage {
                     let ents_len = entities.len();
                     let filter = &mut self.entity_filter;
                     let new_view = &mut self.new_view;
+                    let camera = &mut self.camera;
+                    let markers = &self.markers;
+                    let mut entities_changed = false;
+                    let model_count = parsed_map.models().len();
+                    let missing_model = |f: &Entity| f.brush_model().map(|f| f >= model_count).unwrap_or(false);
                     const VDF_EXT: [&str; 3] = ["txt", "kv", "vdf"];
                     const VDF_FLT: &str = "KeyValue";
                     egui::Window::new(format!("[{}] Entity view", self.name))
@@ -528,6 +1328,15 @@ impl MapWindowStage {
                                     let data = entities.iter().map(|f| f.string.as_str()).collect::<Vec<&str>>().join("\n");
                                     save_picker(VDF_FLT, &VDF_EXT, data.as_bytes());
                                 }
+                                // same text the BSP save writes, as `<map>_l_0.lmp` it overrides the lump without touching the map
+                                if ui.button("Export modified as .lmp").clicked() {
+                                    let data = entities.iter().map(|f| f.string.as_str()).collect::<Vec<&str>>().join("\n") + "\n\0";
+                                    let version = lump_helper!(&parsed_map.lumps[0], BSPLump::Entities(v) => v).base.version;
+                                    let lmp = crate::bsp::write_lmp(0, version, parsed_map.iteration, data.as_bytes());
+                                    if let Err(v) = save_picker("Lump file", &["lmp"], &lmp) {
+                                        eprintln!("{}", v);
+                                    }
+                                }
                             });
                             if ui.button("Replace KV from file").clicked() {
                                 if let Some((_, data)) = file_picker(VDF_FLT, &VDF_EXT) {
@@ -535,11 +1344,20 @@ impl MapWindowStage {
                                         std::str::from_utf8_unchecked(&data)
                                     });
                                     *mutref = 0;
+                                    entities_changed = true;
                                 }
                             }
                             if ui.button("Reset entities (No confirmation)").clicked() {
                                 *mutref = 0;
                                 *entities = kv::parse_ents_hacky(lump_helper!(&parsed_map.lumps[0], BSPLump::Entities(v) => v).string.as_str());
+                                entities_changed = true;
+                            }
+                            let broken = entities.iter().filter(|f| missing_model(f)).count();
+                            if broken > 0 {
+                                ui.colored_label(
+                                    egui::color::Color32::RED,
+                                    format!("{} entities point at brush models that don't exist", broken),
+                                );
                             }
                             ui.checkbox(new_view, "All in one view");
                             ui.horizontal(|ui| {
@@ -561,17 +1379,34 @@ impl MapWindowStage {
                                                     continue;
                                                 }
                                             }
-                                            ui.selectable_value(mutref, i, format!("{}: {}{}", i, entities[i].pretty_name(), if entities[i].dirty {
+                                            ui.selectable_value(mutref, i, format!("{}: {}{}{}", i, entities[i].pretty_name(), if entities[i].dirty {
                                                 " *"
                                             } else {
                                                 ""
+                                            }, if missing_model(&entities[i]) {
+                                                " (missing model)"
+                                            } else {
+                                                ""
                                             }));
                                         }
                                     }
                                 );
 
+                                ui.horizontal(|ui| {
+                                    if let Some(marker) = markers.iter().find(|f| f.entity == *mutref) {
+                                        if ui.button("Go to").clicked() {
+                                            camera.look_at_box(marker.mins, marker.maxs);
+                                        }
+                                    }
+                                    if let Some(model) = entities[*mutref].brush_model() {
+                                        let (colour, text) = brush_model_label(parsed_map, model);
+                                        ui.colored_label(colour, text);
+                                    }
+                                });
+
                                 if ui.code_editor(&mut entities[*mutref].string).changed() {
                                     entities[*mutref].dirty = true;
+                                    entities_changed = true;
                                 }
                             } else {
                                 for ent in entities {
@@ -581,16 +1416,642 @@ impl MapWindowStage {
                                         }
                                     }
 
+                                    if let Some(model) = ent.brush_model() {
+                                        let (colour, text) = brush_model_label(parsed_map, model);
+                                        ui.colored_label(colour, text);
+                                    }
                                     if ui.code_editor(&mut ent.string).changed() {
                                         ent.dirty = true;
+                                        entities_changed = true;
                                     }
                                 }
                             }
                         });
+
+                    if entities_changed {
+                        self.update_markers();
+                    }
                 }
             }
         }
 
+        self.materials_ui(egui_ctx);
+        self.props_ui(egui_ctx);
+        self.displacements_ui(egui_ctx);
+        self.brushes_ui(egui_ctx);
+        self.cubemaps_ui(egui_ctx);
+        self.overlays_ui(egui_ctx);
+        self.lights_ui(egui_ctx);
+        self.physics_ui(egui_ctx);
+
         return grabbed;
     }
+
+    fn materials_ui(&mut self, egui_ctx: &CtxRef) {
+        let materials = &self.materials;
+        let filter = &mut self.material_filter;
+        let unpacked = &mut self.unpacked_materials;
+        egui::Window::new(format!("[{}] Materials", self.name))
+            .open(&mut self.views.materials)
+            .resizable(true)
+            .scroll(true)
+            .default_width(512.0)
+            .show(egui_ctx, |ui| {
+                ui.label(format!(
+                    "{} materials, {} packed",
+                    materials.len(),
+                    materials.iter().filter(|f| f.packed).count()
+                ));
+                ui.checkbox(unpacked, "Show only ones not in the pakfile");
+                ui.horizontal(|ui| {
+                    ui.label("Search");
+                    ui.text_edit_singleline(filter);
+                });
+                let filter = filter.to_lowercase();
+                for material in materials {
+                    if *unpacked && material.packed {
+                        continue;
+                    }
+                    if !filter.is_empty() && !material.name.contains(filter.as_str()) {
+                        continue;
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label(&material.name);
+                        ui.label(format!("{} faces", material.faces));
+                        if material.packed {
+                            ui.colored_label(egui::color::Color32::GREEN, "packed");
+                        }
+                    });
+                }
+            });
+    }
+
+    fn props_ui(&mut self, egui_ctx: &CtxRef) {
+        let parsed_map = if let Some(v) = &self.parsed_map {
+            v
+        } else {
+            return;
+        };
+        let static_props = if let Some(v) = parsed_map.static_props() {
+            v
+        } else {
+            return;
+        };
+        let game_lumps = match &parsed_map.lumps[35] {
+            BSPLump::Game(v) => v
+                .entries
+                .iter()
+                .map(|f| {
+                    let compressed = if f.flags & crate::bsp::GAMELUMP_COMPRESSED != 0 {
+                        " (LZMA)"
+                    } else {
+                        ""
+                    };
+                    format!("{} v{}{}", f.name(), f.version, compressed)
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            _ => String::new(),
+        };
+        let models = &self.prop_models;
+        let filter = &mut self.prop_filter;
+        let missing_only = &mut self.missing_props;
+        egui::Window::new(format!("[{}] Props", self.name))
+            .open(&mut self.views.props)
+            .resizable(true)
+            .scroll(true)
+            .default_width(712.0)
+            .show(egui_ctx, |ui| {
+                ui.label(format!(
+                    "sprp v{} | {} props | {} models | {} missing",
+                    static_props.version,
+                    static_props.props.len(),
+                    models.len(),
+                    models.iter().filter(|f| f.missing()).count()
+                ));
+                ui.label(format!("Game lumps: {}", game_lumps));
+                ui.checkbox(missing_only, "Show only missing models");
+                ui.horizontal(|ui| {
+                    ui.label("Search");
+                    ui.text_edit_singleline(filter);
+                });
+                let filter = filter.to_lowercase();
+                for (i, prop) in static_props.props.iter().enumerate() {
+                    let model = models.get(prop.model as usize);
+                    if *missing_only && !model.map(|f| f.missing()).unwrap_or(true) {
+                        continue;
+                    }
+                    let name = model.map(|f| f.name.as_str()).unwrap_or("<invalid model index>");
+                    if !filter.is_empty() && !name.to_lowercase().contains(filter.as_str()) {
+                        continue;
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{}:", i));
+                        match model {
+                            Some(model) if model.packed => {
+                                ui.colored_label(egui::color::Color32::GREEN, name);
+                            }
+                            Some(model) if !model.provided.is_empty() => {
+                                ui.label(name);
+                                ui.label(format!("[{}]", model.provided[0].source));
+                            }
+                            _ => {
+                                ui.colored_label(egui::color::Color32::RED, format!("{} (missing)", name));
+                            }
+                        }
+                    });
+                    let [x, y, z] = prop.origin;
+                    let [pitch, yaw, roll] = prop.angles;
+                    ui.label(format!(
+                        "    origin {:.1} {:.1} {:.1} | angles {:.1} {:.1} {:.1} | skin {} | fade {:.0}-{:.0}",
+                        x, y, z, pitch, yaw, roll, prop.skin, prop.fade_min, prop.fade_max
+                    ));
+                }
+            });
+    }
+
+    fn displacements_ui(&mut self, egui_ctx: &CtxRef) {
+        let parsed_map = if let Some(v) = &self.parsed_map {
+            v
+        } else {
+            return;
+        };
+        egui::Window::new(format!("[{}] Displacements", self.name))
+            .open(&mut self.views.displacements)
+            .resizable(true)
+            .scroll(true)
+            .default_width(512.0)
+            .show(egui_ctx, |ui| {
+                let disp_infos = parsed_map.disp_infos();
+                ui.label(format!(
+                    "{} displacements | {} vertices | {} triangles",
+                    disp_infos.len(),
+                    parsed_map.disp_verts().len(),
+                    parsed_map.disp_tris().len()
+                ));
+                for (i, disp) in disp_infos.iter().enumerate() {
+                    let size = disp.size();
+                    let first = disp.disp_vert_start.max(0) as usize;
+                    let alphas = parsed_map
+                        .disp_verts()
+                        .get(first..first + size * size)
+                        .map(|f| f.iter().map(|v| v.alpha).collect::<Vec<_>>());
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{}: face {} | power {} ({}x{})",
+                            i, disp.map_face, disp.power, size, size
+                        ));
+                        match &alphas {
+                            Some(alphas) => {
+                                let max = alphas.iter().cloned().fold(0.0, f32::max);
+                                if max > 0.0 {
+                                    let avg = alphas.iter().sum::<f32>() / alphas.len() as f32;
+                                    ui.label(format!("alpha avg {:.0} max {:.0}", avg, max));
+                                } else {
+                                    ui.label("no alpha");
+                                }
+                            }
+                            None => {
+                                ui.colored_label(
+                                    egui::color::Color32::RED,
+                                    "vertices outside of the lump",
+                                );
+                            }
+                        }
+                        if !(2..=4).contains(&disp.power) {
+                            ui.colored_label(egui::color::Color32::RED, "invalid power");
+                        }
+                    });
+                }
+            });
+    }
+
+    fn brushes_ui(&mut self, egui_ctx: &CtxRef) {
+        // how many of the biggest brushes to list, enough to spot a map wide clip
+        const LARGEST: usize = 25;
+
+        let world_volume = self
+            .parsed_map
+            .as_ref()
+            .and_then(|f| f.models().first().copied())
+            .map(|f| {
+                let size = math::sub(f.maxs, f.mins);
+                size[0] * size[1] * size[2]
+            })
+            .unwrap_or(0.0);
+        let brushes = &self.brushes;
+        let overlay = &mut self.brush_overlay;
+        let camera = &mut self.camera;
+        egui::Window::new(format!("[{}] Brushes", self.name))
+            .open(&mut self.views.brushes)
+            .resizable(true)
+            .scroll(true)
+            .default_width(512.0)
+            .show(egui_ctx, |ui| {
+                ui.label(format!("{} brushes, ticked ones are drawn over the Map view", brushes.len()));
+                egui::Grid::new("brush_categories").striped(true).num_columns(4).show(ui, |ui| {
+                    for (i, (name, _, [r, g, b, _])) in BRUSH_CATEGORIES.iter().enumerate() {
+                        let colour = egui::color::Color32::from_rgb((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8);
+                        let matching = brushes.iter().filter(|f| f.category == i);
+                        let count = matching.clone().count();
+                        let volume = matching.map(|f| f.volume()).sum::<f32>();
+
+                        ui.checkbox(&mut overlay[i], "");
+                        ui.colored_label(colour, name);
+                        ui.label(count);
+                        // bounds overlap, this is only a rough idea of how much of the map it covers
+                        if world_volume > 0.0 {
+                            ui.label(format!("{:.2}% of the map bounds", volume / world_volume * 100.0));
+                        } else {
+                            ui.label("");
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                ui.label("Largest of the ticked categories");
+                let mut largest = brushes
+                    .iter()
+                    .filter(|f| overlay[f.category])
+                    .collect::<Vec<_>>();
+                largest.sort_by(|a, b| b.volume().partial_cmp(&a.volume()).unwrap_or(std::cmp::Ordering::Equal));
+                for brush in largest.into_iter().take(LARGEST) {
+                    let size = math::sub(brush.maxs, brush.mins);
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{}: {} | {:.0} x {:.0} x {:.0} | contents 0x{:x}",
+                            brush.index,
+                            BRUSH_CATEGORIES[brush.category].0,
+                            size[0],
+                            size[1],
+                            size[2],
+                            brush.contents
+                        ));
+                        if ui.small_button("Go to").clicked() {
+                            camera.look_at_box(brush.mins, brush.maxs);
+                        }
+                    });
+                }
+            });
+    }
+
+    fn cubemaps_ui(&mut self, egui_ctx: &CtxRef) {
+        let (has_ldr, has_hdr) = if let Some(v) = &self.parsed_map {
+            (v.lumps[8].base().size > 0, v.lumps[53].base().size > 0)
+        } else {
+            return;
+        };
+        // no lighting at all still gets LDR ones from buildcubemaps
+        let has_ldr = has_ldr || !has_hdr;
+        let missing = |f: &Cubemap| (has_ldr && f.ldr.is_none()) || (has_hdr && f.hdr.is_none());
+
+        let cubemaps = &self.cubemaps;
+        let orphans = &self.orphaned_cubemaps;
+        let textures = &self.textures;
+        let camera = &mut self.camera;
+        egui::Window::new(format!("[{}] Cubemaps", self.name))
+            .open(&mut self.views.cubemaps)
+            .resizable(true)
+            .scroll(true)
+            .default_width(512.0)
+            .show(egui_ctx, |ui| {
+                ui.label(format!(
+                    "{} samples | {} missing | {} orphaned",
+                    cubemaps.len(),
+                    cubemaps.iter().filter(|f| missing(f)).count(),
+                    orphans.len()
+                ));
+                if cubemaps.is_empty() {
+                    ui.label("No env_cubemap in the map, reflections fall back to the sky");
+                }
+                if !cubemaps.is_empty() && cubemaps.iter().all(|f| f.ldr.is_none() && f.hdr.is_none()) {
+                    ui.colored_label(egui::color::Color32::RED, "Nothing packed, buildcubemaps was never run");
+                }
+
+                for (i, cubemap) in cubemaps.iter().enumerate() {
+                    let sample = &cubemap.sample;
+                    ui.horizontal(|ui| {
+                        if let Some(texture) = cubemap.ldr.or(cubemap.hdr).and_then(|f| textures.get(f)) {
+                            ui.image(
+                                egui::TextureId::User(texture.texture.gl_internal_id() as u64),
+                                [128.0, 96.0],
+                            );
+                        }
+                        ui.vertical(|ui| {
+                            let [x, y, z] = sample.origin;
+                            ui.label(format!(
+                                "{}: {} {} {} | {}x{}",
+                                i,
+                                x,
+                                y,
+                                z,
+                                sample.resolution(),
+                                sample.resolution()
+                            ));
+                            for (name, packed, expected) in &[("LDR", cubemap.ldr, has_ldr), ("HDR", cubemap.hdr, has_hdr)] {
+                                match (packed, expected) {
+                                    (Some(_), _) => {
+                                        ui.label(format!("{} packed", name));
+                                    }
+                                    (None, true) => {
+                                        ui.colored_label(egui::color::Color32::RED, format!("{} missing", name));
+                                    }
+                                    (None, false) => {}
+                                }
+                            }
+                            if ui.small_button("Go to").clicked() {
+                                let origin = [x as f32, y as f32, z as f32];
+                                camera.look_at_box(math::sub(origin, [16.0; 3]), math::add(origin, [16.0; 3]));
+                            }
+                        });
+                    });
+                }
+
+                if !orphans.is_empty() {
+                    ui.separator();
+                    ui.label("Orphaned");
+                    for (texture, reason) in orphans {
+                        if let Some(texture) = textures.get(*texture) {
+                            ui.colored_label(egui::color::Color32::YELLOW, format!("{}: {}", texture.name, reason));
+                        }
+                    }
+                }
+            });
+    }
+
+    fn overlays_ui(&mut self, egui_ctx: &CtxRef) {
+        let parsed_map = if let Some(v) = &self.parsed_map {
+            v
+        } else {
+            return;
+        };
+        let camera = &mut self.camera;
+        egui::Window::new(format!("[{}] Overlays", self.name))
+            .open(&mut self.views.overlays)
+            .resizable(true)
+            .scroll(true)
+            .default_width(512.0)
+            .show(egui_ctx, |ui| {
+                let face_total = parsed_map.faces().len();
+                let all = parsed_map
+                    .overlays()
+                    .iter()
+                    .map(|f| (f, false))
+                    .chain(parsed_map.water_overlays().iter().map(|f| (f, true)))
+                    .collect::<Vec<(&Overlay, bool)>>();
+                let max_faces = |water: bool| {
+                    if water {
+                        WATER_OVERLAY_MAX_FACES
+                    } else {
+                        OVERLAY_MAX_FACES
+                    }
+                };
+                let broken = all
+                    .iter()
+                    .filter(|(f, water)| {
+                        let (invalid, too_many) = f.invalid_faces(face_total, max_faces(*water));
+                        !invalid.is_empty() || too_many
+                    })
+                    .count();
+                ui.label(format!(
+                    "{} overlays | {} water overlays",
+                    parsed_map.overlays().len(),
+                    parsed_map.water_overlays().len()
+                ));
+                if broken > 0 {
+                    ui.colored_label(
+                        egui::color::Color32::RED,
+                        format!("{} overlays point at faces that don't exist, some clients crash on these", broken),
+                    );
+                }
+
+                for (overlay, water) in all {
+                    let material = parsed_map
+                        .tex_info()
+                        .get(overlay.texinfo as usize)
+                        .and_then(|f| parsed_map.tex_data().get(f.texdata as usize))
+                        .and_then(|f| parsed_map.material_name(f))
+                        .unwrap_or("<invalid texinfo>");
+                    let (invalid, too_many) = overlay.invalid_faces(face_total, max_faces(water));
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{}{}: {} | {} faces | order {}",
+                            if water { "water " } else { "" },
+                            overlay.id,
+                            material,
+                            overlay.face_count,
+                            overlay.render_order
+                        ));
+                        if ui.small_button("Go to").clicked() {
+                            let corners = overlay.corners();
+                            let (mut mins, mut maxs) = (corners[0], corners[0]);
+                            for corner in &corners[1..] {
+                                for i in 0..3 {
+                                    mins[i] = mins[i].min(corner[i]);
+                                    maxs[i] = maxs[i].max(corner[i]);
+                                }
+                            }
+                            camera.look_at_box(mins, maxs);
+                        }
+                    });
+                    if too_many {
+                        ui.colored_label(
+                            egui::color::Color32::RED,
+                            format!("face count {} is over the limit of {}", overlay.face_count, max_faces(water)),
+                        );
+                    }
+                    if !invalid.is_empty() {
+                        ui.colored_label(
+                            egui::color::Color32::RED,
+                            format!("invalid faces {:?}, the map has {}", invalid, face_total),
+                        );
+                    }
+                }
+            });
+    }
+
+    fn lights_ui(&mut self, egui_ctx: &CtxRef) {
+        // per light set, more than this and vrad and the engine both start to crawl
+        const MANY_LIGHTS: usize = 1024;
+        // how many individual lights to list
+        const LISTED: usize = 500;
+
+        let parsed_map = if let Some(v) = &self.parsed_map {
+            v
+        } else {
+            return;
+        };
+        let has_sky = parsed_map
+            .tex_info()
+            .iter()
+            .any(|f| f.flags & (crate::bsp::SURF_SKY | crate::bsp::SURF_SKY2D) != 0);
+        let camera = &mut self.camera;
+        egui::Window::new(format!("[{}] Lights", self.name))
+            .open(&mut self.views.lights)
+            .resizable(true)
+            .scroll(true)
+            .default_width(512.0)
+            .show(egui_ctx, |ui| {
+                for (name, lights) in &[("LDR", parsed_map.world_lights()), ("HDR", parsed_map.world_lights_hdr())] {
+                    if lights.is_empty() {
+                        continue;
+                    }
+
+                    let mut counts = Vec::<(&str, usize)>::new();
+                    for light in lights.iter() {
+                        match counts.iter_mut().find(|f| f.0 == light.kind.name()) {
+                            Some(v) => v.1 += 1,
+                            None => counts.push((light.kind.name(), 1)),
+                        }
+                    }
+                    ui.heading(format!("{}: {} lights", name, lights.len()));
+                    ui.label(
+                        counts
+                            .iter()
+                            .map(|(kind, count)| format!("{} {}", count, kind))
+                            .collect::<Vec<_>>()
+                            .join(" | "),
+                    );
+
+                    if lights.len() > MANY_LIGHTS {
+                        ui.colored_label(
+                            egui::color::Color32::YELLOW,
+                            format!("Over {} lights, consider merging or turning some into lightmapped-only", MANY_LIGHTS),
+                        );
+                    }
+                    let count = |kind: EmitType| lights.iter().filter(|f| f.kind == kind).count();
+                    match (count(EmitType::Skylight), count(EmitType::SkyAmbient)) {
+                        (0, 0) if has_sky => {
+                            ui.colored_label(
+                                egui::color::Color32::YELLOW,
+                                "No light_environment, the sky doesn't light anything",
+                            );
+                        }
+                        (sun, ambient) if sun > 1 || ambient > 1 => {
+                            ui.colored_label(
+                                egui::color::Color32::RED,
+                                "More than one light_environment, vrad only uses one of them",
+                            );
+                        }
+                        _ => {}
+                    }
+
+                    ui.collapsing(format!("{} lights", name), |ui| {
+                        for (i, light) in lights.iter().enumerate().take(LISTED) {
+                            ui.horizontal(|ui| {
+                                ui.label(light_label(i, light));
+                                if let EmitType::Skylight | EmitType::SkyAmbient = light.kind {
+                                    return;
+                                }
+                                if ui.small_button("Go to").clicked() {
+                                    let origin = light.origin;
+                                    camera.look_at_box(math::sub(origin, [32.0; 3]), math::add(origin, [32.0; 3]));
+                                }
+                            });
+                        }
+                        if lights.len() > LISTED {
+                            ui.label(format!("... and {} more", lights.len() - LISTED));
+                        }
+                    });
+                }
+                if parsed_map.world_lights().is_empty() && parsed_map.world_lights_hdr().is_empty() {
+                    ui.label("No world lights, the map was compiled without VRAD or has no lights");
+                }
+            });
+    }
+
+    fn physics_ui(&mut self, egui_ctx: &CtxRef) {
+        // single solids past this are usually a brush entity that should've been split or made nonsolid
+        const HUGE_SOLID: usize = 512 * 1024;
+
+        let parsed_map = if let Some(v) = &self.parsed_map {
+            v
+        } else {
+            return;
+        };
+        let camera = &mut self.camera;
+        egui::Window::new(format!("[{}] Physics", self.name))
+            .open(&mut self.views.physics)
+            .resizable(true)
+            .scroll(true)
+            .default_width(512.0)
+            .show(egui_ctx, |ui| {
+                let lump = if let Some(v) = parsed_map.phys_collide() {
+                    v
+                } else {
+                    ui.label("No physcollide lump");
+                    return;
+                };
+                let model_total = parsed_map.models().len();
+                let solids = lump.models.iter().map(|f| f.solids.len()).sum::<usize>();
+                ui.label(format!(
+                    "{} models | {} solids | {}",
+                    lump.models.len(),
+                    solids,
+                    bytesize::to_string(lump.base.size as u64, false)
+                ));
+                for problem in &lump.problems {
+                    ui.colored_label(egui::color::Color32::RED, problem);
+                }
+
+                for (i, model) in lump.models.iter().enumerate() {
+                    let largest = model.solids.iter().map(|f| f.size).max().unwrap_or(0);
+                    let bounds = parsed_map.models().get(model.model as usize).filter(|_| model.model >= 0);
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "*{}: {} solids | {} data | {} keys",
+                            model.model,
+                            model.solids.len(),
+                            bytesize::to_string(model.data_size as u64, false),
+                            bytesize::to_string(model.key_size as u64, false)
+                        ));
+                        if let Some(bounds) = bounds {
+                            if ui.small_button("Go to").clicked() {
+                                camera.look_at_box(bounds.mins, bounds.maxs);
+                            }
+                        }
+                    });
+                    if model.model < 0 || model.model as usize >= model_total {
+                        ui.colored_label(
+                            egui::color::Color32::RED,
+                            format!("model {} doesn't exist, the map has {}", model.model, model_total),
+                        );
+                    }
+                    if largest > HUGE_SOLID {
+                        ui.colored_label(
+                            egui::color::Color32::YELLOW,
+                            format!("a {} solid, servers can choke on collision this big", bytesize::to_string(largest as u64, false)),
+                        );
+                    }
+                    for problem in &model.problems {
+                        ui.colored_label(egui::color::Color32::RED, problem);
+                    }
+
+                    ui.collapsing(format!("Model {} details", i), |ui| {
+                        for (j, solid) in model.solids.iter().enumerate() {
+                            ui.label(match solid.vphy {
+                                Some((version, kind)) => format!(
+                                    "{}: {} at {} | VPHY v{} type {}",
+                                    j,
+                                    bytesize::to_string(solid.size as u64, false),
+                                    solid.offset,
+                                    version,
+                                    kind
+                                ),
+                                None => format!(
+                                    "{}: {} at {} | old format",
+                                    j,
+                                    bytesize::to_string(solid.size as u64, false),
+                                    solid.offset
+                                ),
+                            });
+                        }
+                        ui.collapsing("Key data", |ui| {
+                            ui.monospace(&model.keydata);
+                        });
+                    });
+                }
+            });
+    }
 }
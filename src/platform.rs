@@ -1,8 +1,26 @@
 #[cfg(target_arch = "wasm32")]
 use std::{cell::RefCell, rc::Rc};
 
-pub fn file_picker_map() -> Option<(String, Vec<u8>)> {
-    file_picker("Map file", &["bsp"])
+// The map and any `<map>_l_<n>.lmp` next to it, numbered from 0 and in the order the engine applies them
+#[cfg(not(target_arch = "wasm32"))]
+pub fn file_picker_map() -> Option<(String, Vec<u8>, Vec<(String, Vec<u8>)>)> {
+    use std::fs;
+
+    let file = rfd::FileDialog::new()
+        .add_filter("Map file", &["bsp"])
+        .pick_file()?;
+    let file_stem = file.file_stem()?.to_str()?.to_string();
+    let vec = fs::read(&file).ok()?;
+
+    let mut lumps = Vec::new();
+    for i in 0.. {
+        let name = format!("{}_l_{}.lmp", file_stem, i);
+        match fs::read(file.with_file_name(&name)) {
+            Ok(data) => lumps.push((name, data)),
+            Err(_) => break,
+        }
+    }
+    Some((file_stem, vec, lumps))
 }
 
 pub fn file_picker_json() -> Option<(String, Vec<u8>)> {
@@ -33,6 +51,18 @@ pub fn file_picker(filter_name: &str, extensions: &[&str]) -> Option<(String, Ve
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+pub fn folder_picker() -> Option<std::path::PathBuf> {
+    rfd::FileDialog::new().pick_folder()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_path_picker(filter_name: &str, extensions: &[&str]) -> Option<std::path::PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter(filter_name, extensions)
+        .save_file()
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn save_picker(
     filter_name: &str,
@@ -96,7 +126,7 @@ extern "C" {
 #[cfg(target_arch = "wasm32")]
 pub fn wasm_file_picker(
     map_vec: *mut Vec<Rc<RefCell<crate::map_window::MapWindowStage>>>,
-    blacklist: *mut Option<crate::blacklist::Blacklist>,
+    blacklist: *mut crate::blacklist::BlacklistSet,
     ctx: *mut miniquad::Context,
     file_type: FileType,
 ) {
@@ -179,7 +209,7 @@ pub extern "C" fn wasm_cb(
             .as_mut()
             .unwrap()
     };
-    let blacklist = unsafe { &mut *(blacklist as *mut Option<crate::blacklist::Blacklist>) };
+    let blacklist = unsafe { &mut *(blacklist as *mut crate::blacklist::BlacklistSet) };
     let ctx = unsafe { &mut *(ctx as *mut miniquad::Context) };
 
     let stem_slice =
@@ -190,7 +220,7 @@ pub extern "C" fn wasm_cb(
     let vec = vec_slice.to_vec();
 
     match file_type {
-        FileType::Map => match MapWindowStage::new(stem, vec, ctx, 256, 256, blacklist.as_ref()) {
+        FileType::Map => match MapWindowStage::new(stem, vec, &[], ctx, 256, 256, blacklist) {
             Ok(v) => {
                 unsafe {
                     console_log(CString::new(format!("OK!")).unwrap().as_ptr());
@@ -214,11 +244,18 @@ pub extern "C" fn wasm_cb(
                         .as_ptr(),
                 );
             };
-            if let Ok(new_blacklist) = crate::blacklist::Blacklist::new(&vec) {
-                *blacklist = Some(new_blacklist);
-                unsafe {
-                    console_log(CString::new("Blacklist parse OK").unwrap().as_ptr());
-                };
+            match crate::blacklist::Blacklist::new(&vec) {
+                Ok(new_blacklist) => {
+                    blacklist.add(stem, new_blacklist);
+                    blacklist.load_error = None;
+                    for map in map_vec.iter() {
+                        map.borrow_mut().recheck(blacklist);
+                    }
+                    unsafe {
+                        console_log(CString::new("Blacklist parse OK").unwrap().as_ptr());
+                    };
+                }
+                Err(v) => blacklist.load_error = Some(format!("{}: {}", stem, v)),
             }
         }
         _ => {
@@ -0,0 +1,200 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+const VPK_SIGNATURE: u32 = 0x55AA_1234;
+const DIR_ARCHIVE: u16 = 0x7FFF;
+
+#[derive(Debug)]
+pub enum VPKError {
+    InvalidHeader(u32),
+    InvalidVersion(u32),
+    InvalidTree(usize),
+}
+
+impl fmt::Display for VPKError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeader(v) => write!(f, "Invalid header: {:08X}!", v),
+            Self::InvalidVersion(v) => write!(f, "Invalid version: {}, must be 1 or 2!", v),
+            Self::InvalidTree(v) => write!(f, "Invalid directory tree at: {:X}!", v),
+        }
+    }
+}
+
+impl Error for VPKError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VPKEntry {
+    pub path: String,
+    pub crc: u32,
+    pub preload: Vec<u8>,
+    pub archive_index: u16,
+    pub offset: u32,
+    pub length: u32,
+}
+
+#[derive(Debug)]
+pub struct VPKDir {
+    pub path: PathBuf, // path to the _dir.vpk
+    pub data_offset: u64, // where embedded (0x7FFF) data starts in the dir file
+    pub entries: Vec<VPKEntry>,
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*buf.get(pos)?, *buf.get(pos + 1)?]))
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    let b = buf.get(pos..pos + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_cstr<'a>(buf: &'a [u8], pos: &mut usize) -> Option<&'a str> {
+    let rest = buf.get(*pos..)?;
+    let len = rest.iter().position(|f| *f == 0)?;
+    *pos += len + 1;
+    std::str::from_utf8(&rest[..len]).ok()
+}
+
+impl VPKDir {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let buf = std::fs::read(path)?;
+
+        let signature = read_u32(&buf, 0).unwrap_or(0);
+        if signature != VPK_SIGNATURE {
+            return Err(Box::new(VPKError::InvalidHeader(signature)));
+        }
+        let version = read_u32(&buf, 4).unwrap_or(0);
+        let header_size = match version {
+            1 => 12,
+            2 => 28,
+            v => return Err(Box::new(VPKError::InvalidVersion(v))),
+        };
+        let tree_size = read_u32(&buf, 8).unwrap_or(0) as usize;
+
+        let tree_end = header_size + tree_size;
+        if tree_end > buf.len() {
+            return Err(Box::new(VPKError::InvalidTree(tree_end)));
+        }
+        let tree = &buf[..tree_end];
+
+        let mut entries = Vec::new();
+        let mut pos = header_size;
+        // ext -> path -> name, each level terminated by an empty string
+        loop {
+            let ext = read_cstr(tree, &mut pos).ok_or(VPKError::InvalidTree(pos))?;
+            if ext.is_empty() {
+                break;
+            }
+            loop {
+                let dir = read_cstr(tree, &mut pos).ok_or(VPKError::InvalidTree(pos))?;
+                if dir.is_empty() {
+                    break;
+                }
+                loop {
+                    let name = read_cstr(tree, &mut pos).ok_or(VPKError::InvalidTree(pos))?;
+                    if name.is_empty() {
+                        break;
+                    }
+
+                    let entry_pos = pos;
+                    let (crc, preload_size, archive_index, offset, length, terminator) = (|| {
+                        Some((
+                            read_u32(tree, pos)?,
+                            read_u16(tree, pos + 4)?,
+                            read_u16(tree, pos + 6)?,
+                            read_u32(tree, pos + 8)?,
+                            read_u32(tree, pos + 12)?,
+                            read_u16(tree, pos + 16)?,
+                        ))
+                    })()
+                    .ok_or(VPKError::InvalidTree(entry_pos))?;
+                    if terminator != 0xFFFF {
+                        return Err(Box::new(VPKError::InvalidTree(entry_pos + 16)));
+                    }
+                    pos += 18;
+
+                    let preload = tree
+                        .get(pos..pos + preload_size as usize)
+                        .ok_or(VPKError::InvalidTree(pos))?
+                        .to_vec();
+                    pos += preload_size as usize;
+
+                    // " " stands for "nothing" in both dir and ext
+                    let mut path = String::new();
+                    if dir != " " {
+                        path.push_str(dir);
+                        path.push('/');
+                    }
+                    path.push_str(name);
+                    if ext != " " {
+                        path.push('.');
+                        path.push_str(ext);
+                    }
+
+                    entries.push(VPKEntry {
+                        path: path.to_lowercase(),
+                        crc,
+                        preload,
+                        archive_index,
+                        offset,
+                        length,
+                    });
+                }
+            }
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            data_offset: tree_end as u64,
+            entries,
+        })
+    }
+
+    pub fn archive_path(&self, index: u16) -> PathBuf {
+        let stem = self
+            .path
+            .file_stem()
+            .and_then(|f| f.to_str())
+            .unwrap_or_default();
+        let base = stem.strip_suffix("_dir").unwrap_or(stem);
+        self.path.with_file_name(format!("{}_{:03}.vpk", base, index))
+    }
+
+    // Reads the whole file, preload bytes included.
+    // Archives are kept open in `files` since games have thousands of entries per archive.
+    pub fn read(
+        &self,
+        entry: &VPKEntry,
+        files: &mut hashbrown::HashMap<u16, std::fs::File>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut data = Vec::with_capacity(entry.preload.len() + entry.length as usize);
+        data.extend_from_slice(&entry.preload);
+        if entry.length > 0 {
+            let offset = if entry.archive_index == DIR_ARCHIVE {
+                self.data_offset + entry.offset as u64
+            } else {
+                entry.offset as u64
+            };
+            let file = match files.entry(entry.archive_index) {
+                hashbrown::hash_map::Entry::Occupied(v) => v.into_mut(),
+                hashbrown::hash_map::Entry::Vacant(v) => {
+                    let path = if entry.archive_index == DIR_ARCHIVE {
+                        self.path.clone()
+                    } else {
+                        self.archive_path(entry.archive_index)
+                    };
+                    v.insert(std::fs::File::open(path)?)
+                }
+            };
+            file.seek(SeekFrom::Start(offset))?;
+            let start = data.len();
+            data.resize(start + entry.length as usize, 0);
+            file.read_exact(&mut data[start..])?;
+        }
+        Ok(data)
+    }
+}
@@ -24,3 +24,539 @@ pub struct VTFHEADER {
     pub numResources: ::std::os::raw::c_uint,
     pub padding3: [::std::os::raw::c_uchar; 8usize],
 }
+
+const VTF_SIGNATURE: [u8; 4] = *b"VTF\0";
+
+const TEXTUREFLAGS_ENVMAP: u32 = 0x4000;
+
+// Resource tags for 7.3+
+const RESOURCE_LOWRES: [u8; 3] = [0x01, 0, 0];
+const RESOURCE_HIGHRES: [u8; 3] = [0x30, 0, 0];
+const MAX_RESOURCES: u32 = 32;
+
+const LOWRES_MAX: u8 = 16;
+const FORMAT_NONE: u32 = 0xFFFF_FFFF;
+const FORMAT_DXT1: u32 = 13;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderProblem {
+    TooSmall(usize),
+    InvalidSignature([u8; 4]),
+    UnsupportedVersion(u32, u32),
+    HeaderSize(u32, usize), // header size, file size
+    NonPowerOfTwo(u16, u16, u16),
+    ZeroSize(u16, u16),
+    Mipmaps(u8, u8), // count, max possible
+    Frames(u16, u16), // frames, first frame
+    UnknownFormat(u32),
+    LowRes(u32, u8, u8), // format, width, height
+    Resources(u32),
+    MissingHighRes,
+    Overflow,
+    Truncated(u64, usize), // needed, file size
+    Decode(String), // vtf crate gave up on something we didn't catch
+}
+
+impl HeaderProblem {
+    // Fatal ones mean we don't even try to hand the file to the vtf crate
+    pub fn is_fatal(&self) -> bool {
+        !matches!(
+            self,
+            Self::NonPowerOfTwo(..) | Self::Frames(..) | Self::LowRes(..)
+        )
+    }
+}
+
+impl std::fmt::Display for HeaderProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooSmall(v) => write!(f, "File is too small for a header: {} bytes", v),
+            Self::InvalidSignature(v) => write!(f, "Invalid signature: {:02X?}", v),
+            Self::UnsupportedVersion(a, b) => write!(f, "Unsupported version: {}.{}", a, b),
+            Self::HeaderSize(a, b) => write!(f, "Header size {} is bogus for {} bytes", a, b),
+            Self::NonPowerOfTwo(w, h, d) => write!(f, "Non power of two size: {}x{}x{}", w, h, d),
+            Self::ZeroSize(w, h) => write!(f, "Zero size: {}x{}", w, h),
+            Self::Mipmaps(a, b) => write!(f, "Mipmap count {} > {}", a, b),
+            Self::Frames(a, b) => write!(f, "Bogus frames: {} (first {})", a, b),
+            Self::UnknownFormat(v) => write!(f, "Unknown image format: {}", v),
+            Self::LowRes(a, w, h) => write!(f, "Bogus low-res image: format {} {}x{}", a, w, h),
+            Self::Resources(v) => write!(f, "Too many resources: {}", v),
+            Self::MissingHighRes => write!(f, "No high-res image resource"),
+            Self::Overflow => write!(f, "Image data size overflows"),
+            Self::Truncated(a, b) => write!(f, "Needs {} bytes, file is {}", a, b),
+            Self::Decode(v) => write!(f, "Decode failed: {}", v),
+        }
+    }
+}
+
+// (block size in pixels, bytes per block)
+fn format_block(format: u32) -> Option<(u64, u64)> {
+    Some(match format {
+        0 | 1 | 11 | 12 | 16 | 23 | 26 => (1, 4), // RGBA8888, ABGR8888, ARGB8888, BGRA8888, BGRX8888, UVWQ8888, UVLX8888
+        2 | 3 | 9 | 10 => (1, 3),                  // RGB888, BGR888 (+bluescreen)
+        4 | 6 | 17 | 18 | 19 | 21 | 22 => (1, 2),  // 565, IA88, 5551, 4444, UV88
+        5 | 7 | 8 => (1, 1),                       // I8, P8, A8
+        13 | 20 => (4, 8),                         // DXT1
+        14 | 15 => (4, 16),                        // DXT3, DXT5
+        24 | 25 => (1, 8),                         // RGBA16161616(F)
+        _ => return None,
+    })
+}
+
+pub fn image_size(format: u32, width: u64, height: u64, depth: u64) -> Option<u64> {
+    let (block, bytes) = format_block(format)?;
+    let w = (width + block - 1) / block;
+    let h = (height + block - 1) / block;
+    w.checked_mul(h)?.checked_mul(depth)?.checked_mul(bytes)
+}
+
+fn max_mipmaps(width: u16, height: u16, depth: u16) -> u8 {
+    let largest = width.max(height).max(depth).max(1);
+    (16 - largest.leading_zeros()) as u8
+}
+
+// Faces per frame. Before 7.5 envmaps carry a spheremap as a 7th face, a -1 first frame means they don't.
+fn envmap_faces(header: &VTFHEADER) -> u64 {
+    if header.flags & TEXTUREFLAGS_ENVMAP == 0 {
+        1
+    } else if header.version[1] < 5 && header.firstFrame != 0xFFFF {
+        7
+    } else {
+        6
+    }
+}
+
+pub fn read_header(data: &[u8]) -> Option<VTFHEADER> {
+    // 7.0/7.1 headers are shorter than our struct, pad with zeroes
+    if data.len() < 64 {
+        return None;
+    }
+    let mut raw = [0u8; std::mem::size_of::<VTFHEADER>()];
+    let len = raw.len().min(data.len());
+    raw[..len].copy_from_slice(&data[..len]);
+    Some(unsafe { std::ptr::read_unaligned(raw.as_ptr().cast::<VTFHEADER>()) })
+}
+
+// Malformed VTFs are a known way to crash clients (and us), so check everything we can before decoding
+pub fn check_header(data: &[u8]) -> Vec<HeaderProblem> {
+    let mut problems = Vec::new();
+
+    let header = if let Some(v) = read_header(data) {
+        v
+    } else {
+        problems.push(HeaderProblem::TooSmall(data.len()));
+        return problems;
+    };
+
+    let signature = header.signature.map(|f| f as u8);
+    if signature != VTF_SIGNATURE {
+        problems.push(HeaderProblem::InvalidSignature(signature));
+        return problems;
+    }
+
+    let version = header.version;
+    if version[0] != 7 || version[1] > 5 {
+        problems.push(HeaderProblem::UnsupportedVersion(version[0], version[1]));
+        return problems;
+    }
+    let minor = version[1];
+
+    let header_size = header.headerSize;
+    let min_header = if minor >= 3 { 80 } else { 64 };
+    if header_size < min_header || header_size as usize > data.len() {
+        problems.push(HeaderProblem::HeaderSize(header_size, data.len()));
+        return problems;
+    }
+
+    let (width, height) = (header.width, header.height);
+    let depth = if minor >= 2 { header.depth.max(1) } else { 1 };
+    if width == 0 || height == 0 {
+        problems.push(HeaderProblem::ZeroSize(width, height));
+        return problems;
+    }
+    if !width.is_power_of_two() || !height.is_power_of_two() || !depth.is_power_of_two() {
+        problems.push(HeaderProblem::NonPowerOfTwo(width, height, depth));
+    }
+
+    let mipmaps = header.mipmapCount;
+    let max_mips = max_mipmaps(width, height, depth);
+    if mipmaps == 0 || mipmaps > max_mips {
+        problems.push(HeaderProblem::Mipmaps(mipmaps, max_mips));
+    }
+
+    let flags = header.flags;
+    let (frames, first_frame) = (header.frames, header.firstFrame);
+    // -1 first frame is how old envmaps say they have no spheremap
+    let no_spheremap = flags & TEXTUREFLAGS_ENVMAP != 0 && minor < 5 && first_frame == 0xFFFF;
+    if frames == 0 || (first_frame >= frames && !no_spheremap) {
+        problems.push(HeaderProblem::Frames(frames, first_frame));
+    }
+    let faces = envmap_faces(&header);
+
+    let format = header.highResImageFormat;
+    if format_block(format).is_none() {
+        problems.push(HeaderProblem::UnknownFormat(format));
+    }
+
+    let lowres_format = header.lowResImageFormat;
+    let (lowres_w, lowres_h) = (header.lowResImageWidth, header.lowResImageHeight);
+    let lowres_ok = match lowres_format {
+        FORMAT_NONE => lowres_w == 0 && lowres_h == 0,
+        FORMAT_DXT1 => {
+            lowres_w <= LOWRES_MAX
+                && lowres_h <= LOWRES_MAX
+                && lowres_w as u16 <= width
+                && lowres_h as u16 <= height
+        }
+        _ => false,
+    };
+    if !lowres_ok {
+        problems.push(HeaderProblem::LowRes(lowres_format, lowres_w, lowres_h));
+    }
+
+    if problems.iter().any(|f| f.is_fatal()) {
+        return problems;
+    }
+
+    // Everything below needs sane sizes to compute
+    let lowres_size = if lowres_format == FORMAT_DXT1 {
+        image_size(FORMAT_DXT1, lowres_w as u64, lowres_h as u64, 1).unwrap_or(0)
+    } else {
+        0
+    };
+    let highres_size = {
+        let mut total = Some(0u64);
+        for mip in 0..mipmaps.min(max_mips) as u32 {
+            total = total.and_then(|t| {
+                let size = image_size(
+                    format,
+                    (width as u64 >> mip).max(1),
+                    (height as u64 >> mip).max(1),
+                    (depth as u64 >> mip).max(1),
+                )?;
+                size.checked_mul(frames as u64)?
+                    .checked_mul(faces)?
+                    .checked_add(t)
+            });
+        }
+        if let Some(v) = total {
+            v
+        } else {
+            problems.push(HeaderProblem::Overflow);
+            return problems;
+        }
+    };
+
+    let (lowres_offset, highres_offset) = if minor >= 3 {
+        let resources = header.numResources;
+        if resources > MAX_RESOURCES {
+            problems.push(HeaderProblem::Resources(resources));
+            return problems;
+        }
+        let dict_end = 80 + resources as usize * 8;
+        if dict_end > header_size as usize {
+            problems.push(HeaderProblem::HeaderSize(header_size, data.len()));
+            return problems;
+        }
+
+        let mut lowres = None;
+        let mut highres = None;
+        for i in 0..resources as usize {
+            let entry = &data[80 + i * 8..88 + i * 8];
+            let offset = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]) as u64;
+            if entry[0..3] == RESOURCE_LOWRES {
+                lowres = Some(offset);
+            } else if entry[0..3] == RESOURCE_HIGHRES {
+                highres = Some(offset);
+            } else if entry[3] & 2 == 0 {
+                // Not inline data, so it must point inside the file
+                if offset > data.len() as u64 {
+                    problems.push(HeaderProblem::Truncated(offset, data.len()));
+                }
+            }
+        }
+
+        if let Some(highres) = highres {
+            (lowres.unwrap_or(header_size as u64), highres)
+        } else {
+            problems.push(HeaderProblem::MissingHighRes);
+            return problems;
+        }
+    } else {
+        (header_size as u64, header_size as u64 + lowres_size)
+    };
+
+    for needed in [
+        lowres_offset.checked_add(lowres_size),
+        highres_offset.checked_add(highres_size),
+    ] {
+        match needed {
+            Some(v) if v > data.len() as u64 => problems.push(HeaderProblem::Truncated(v, data.len())),
+            None => problems.push(HeaderProblem::Overflow),
+            _ => {}
+        }
+    }
+
+    problems
+}
+
+#[derive(Debug)]
+pub struct DecodedImage {
+    pub width: u16,
+    pub height: u16,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Malformed(HeaderProblem),
+    UnsupportedImageFormat(::vtf::ImageFormat),
+}
+
+fn decode_problem<E: std::fmt::Debug>(e: E) -> DecodeError {
+    DecodeError::Malformed(HeaderProblem::Decode(format!("{:?}", e)))
+}
+
+// RGBA8 of the first frame of the largest mip, converting what the vtf crate can't handle on its own.
+// Only headers check_header is happy with reach the vtf crate, it indexes the file without looking.
+pub fn decode_rgba8(file_data: &[u8]) -> Result<DecodedImage, DecodeError> {
+    if let Some(v) = check_header(file_data).into_iter().find(|f| f.is_fatal()) {
+        return Err(DecodeError::Malformed(v));
+    }
+
+    // this library sucks... I needed to fork it just to make it work properly cuz no one tests when accepts PRs...
+    let mut data_vec = file_data.to_vec();
+    let vtf = ::vtf::from_bytes(&mut data_vec)
+        .map_err(decode_problem)?;
+    let image = vtf.highres_image;
+
+    // This library is so bad: UnsupportedImageFormat(Rgba16161616f)
+    let rgba = if let Ok(decoded) = image.decode(0) {
+        decoded.into_rgba8().to_vec()
+    } else {
+        match image.format {
+            ::vtf::ImageFormat::Rgba16161616f => {
+                let image_data = image
+                    .get_frame(0)
+                    .map_err(decode_problem)?;
+                // RGBA8
+                image_data
+                    .chunks_exact(2)
+                    .map(|f| {
+                        let chan = half::f16::from_le_bytes([f[0], f[1]]);
+                        (chan.to_f32().max(0.0).min(1.0) * 255.0) as u8
+                    })
+                    .collect()
+            }
+            ::vtf::ImageFormat::Abgr8888 => {
+                let mut image_data = image
+                    .get_frame(0)
+                    .map_err(decode_problem)?
+                    .to_vec();
+                for pixel in image_data.chunks_exact_mut(4) {
+                    pixel.reverse();
+                }
+                image_data
+            }
+            v => return Err(DecodeError::UnsupportedImageFormat(v)),
+        }
+    };
+
+    // the texture upload reads width * height pixels no matter what we hand it
+    if rgba.len() != image.width as usize * image.height as usize * 4 {
+        return Err(DecodeError::Malformed(HeaderProblem::Decode(format!(
+            "{} bytes of pixels for {}x{}",
+            rgba.len(),
+            image.width,
+            image.height
+        ))));
+    }
+
+    Ok(DecodedImage {
+        width: image.width,
+        height: image.height,
+        rgba,
+    })
+}
+
+pub fn is_envmap(data: &[u8]) -> bool {
+    read_header(data)
+        .map(|f| f.flags & TEXTUREFLAGS_ENVMAP != 0)
+        .unwrap_or(false)
+}
+
+// Where the high-res mip chain starts, same lookup check_header does
+fn highres_offset(data: &[u8], header: &VTFHEADER) -> Option<u64> {
+    let minor = header.version[1];
+    if minor < 3 {
+        let lowres = if header.lowResImageFormat == FORMAT_DXT1 {
+            image_size(
+                FORMAT_DXT1,
+                header.lowResImageWidth as u64,
+                header.lowResImageHeight as u64,
+                1,
+            )?
+        } else {
+            0
+        };
+        return Some(header.headerSize as u64 + lowres);
+    }
+
+    (0..header.numResources.min(MAX_RESOURCES) as usize)
+        .filter_map(|i| data.get(80 + i * 8..88 + i * 8))
+        .find(|f| f[0..3] == RESOURCE_HIGHRES)
+        .map(|f| u32::from_le_bytes([f[4], f[5], f[6], f[7]]) as u64)
+}
+
+// The 6 faces of the first frame of an envmap, each decoded on its own as a plain 7.2 VTF
+pub fn decode_faces(file_data: &[u8]) -> Result<Vec<DecodedImage>, DecodeError> {
+    if let Some(v) = check_header(file_data).into_iter().find(|f| f.is_fatal()) {
+        return Err(DecodeError::Malformed(v));
+    }
+    let header = read_header(file_data).ok_or(DecodeError::Malformed(HeaderProblem::TooSmall(file_data.len())))?;
+    let broken = || DecodeError::Malformed(HeaderProblem::Decode("not a cubemap".to_string()));
+    if header.flags & TEXTUREFLAGS_ENVMAP == 0 {
+        return Err(broken());
+    }
+
+    let faces = envmap_faces(&header);
+    let (format, width, height) = (header.highResImageFormat, header.width as u64, header.height as u64);
+    let frames = header.frames as u64;
+    let per_mip = |mip: u32| {
+        image_size(format, (width >> mip).max(1), (height >> mip).max(1), 1)
+            .and_then(|f| f.checked_mul(frames))
+            .and_then(|f| f.checked_mul(faces))
+    };
+
+    // smallest mip first, the full size one is last
+    let mut offset = highres_offset(file_data, &header).ok_or_else(broken)?;
+    for mip in 1..header.mipmapCount as u32 {
+        offset = per_mip(mip)
+            .and_then(|f| offset.checked_add(f))
+            .ok_or(DecodeError::Malformed(HeaderProblem::Overflow))?;
+    }
+    let face_size = image_size(format, width, height, 1).ok_or_else(broken)?;
+
+    let mut fake = header;
+    fake.version = [7, 2];
+    fake.headerSize = std::mem::size_of::<VTFHEADER>() as u32;
+    fake.flags &= !TEXTUREFLAGS_ENVMAP;
+    fake.frames = 1;
+    fake.firstFrame = 0;
+    fake.mipmapCount = 1;
+    fake.lowResImageFormat = FORMAT_NONE;
+    fake.lowResImageWidth = 0;
+    fake.lowResImageHeight = 0;
+    fake.depth = 1;
+    fake.numResources = 0;
+    let fake_header = unsafe {
+        std::slice::from_raw_parts(
+            (&fake as *const VTFHEADER).cast::<u8>(),
+            std::mem::size_of::<VTFHEADER>(),
+        )
+    };
+
+    (0..6)
+        .map(|face| {
+            let start = face_size.checked_mul(face).and_then(|f| f.checked_add(offset));
+            let end = start.and_then(|f| f.checked_add(face_size));
+            let data = match (start, end) {
+                (Some(start), Some(end)) if end <= file_data.len() as u64 => {
+                    &file_data[start as usize..end as usize]
+                }
+                (_, Some(end)) => {
+                    return Err(DecodeError::Malformed(HeaderProblem::Truncated(end, file_data.len())))
+                }
+                _ => return Err(DecodeError::Malformed(HeaderProblem::Overflow)),
+            };
+            let mut single = fake_header.to_vec();
+            single.extend_from_slice(data);
+            decode_rgba8(&single)
+        })
+        .collect()
+}
+
+// Faces laid out as a horizontal cross, up and down above and below the front
+pub fn cubemap_cross(faces: &[DecodedImage]) -> Option<DecodedImage> {
+    // (face, column, row), faces are stored right, left, back, front, up, down
+    const LAYOUT: [(usize, usize, usize); 6] = [(0, 2, 1), (1, 0, 1), (2, 3, 1), (3, 1, 1), (4, 1, 0), (5, 1, 2)];
+
+    let size = faces.first()?.width as usize;
+    if faces.len() < 6 || faces.iter().any(|f| f.width as usize != size || f.height as usize != size) {
+        return None;
+    }
+
+    let (width, height) = (size * 4, size * 3);
+    let mut rgba = vec![0u8; width * height * 4];
+    for &(face, col, row) in &LAYOUT {
+        let image = &faces[face];
+        for y in 0..size {
+            let src = y * size * 4;
+            let dst = ((row * size + y) * width + col * size) * 4;
+            rgba[dst..dst + size * 4].copy_from_slice(image.rgba.get(src..src + size * 4)?);
+        }
+    }
+
+    Some(DecodedImage {
+        width: width as u16,
+        height: height as u16,
+        rgba,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4x4 RGBA8888 envmap with a single mip, `faces` worth of image data after the header
+    fn envmap(minor: u32, first_frame: u16, faces: usize) -> Vec<u8> {
+        let header_size = if minor >= 3 { 88 } else { 80 };
+        let mut data = vec![0u8; header_size];
+        data[0..4].copy_from_slice(&VTF_SIGNATURE);
+        data[4..8].copy_from_slice(&7u32.to_le_bytes());
+        data[8..12].copy_from_slice(&minor.to_le_bytes());
+        data[12..16].copy_from_slice(&(header_size as u32).to_le_bytes());
+        data[16..18].copy_from_slice(&4u16.to_le_bytes());
+        data[18..20].copy_from_slice(&4u16.to_le_bytes());
+        data[20..24].copy_from_slice(&TEXTUREFLAGS_ENVMAP.to_le_bytes());
+        data[24..26].copy_from_slice(&1u16.to_le_bytes());
+        data[26..28].copy_from_slice(&first_frame.to_le_bytes());
+        data[52..56].copy_from_slice(&0u32.to_le_bytes());
+        data[56] = 1;
+        data[57..61].copy_from_slice(&FORMAT_NONE.to_le_bytes());
+        data[63..65].copy_from_slice(&1u16.to_le_bytes());
+        if minor >= 3 {
+            data[68..72].copy_from_slice(&1u32.to_le_bytes());
+            data[80..84].copy_from_slice(&[RESOURCE_HIGHRES[0], 0, 0, 0]);
+            data[84..88].copy_from_slice(&(header_size as u32).to_le_bytes());
+        }
+        data.resize(header_size + faces * 4 * 4 * 4, 0);
+        data
+    }
+
+    #[test]
+    fn envmap_without_spheremap() {
+        for minor in [2, 4] {
+            assert_eq!(check_header(&envmap(minor, 0xFFFF, 6)), vec![]);
+        }
+    }
+
+    #[test]
+    fn envmap_with_spheremap() {
+        for minor in [2, 4] {
+            assert_eq!(check_header(&envmap(minor, 0, 7)), vec![]);
+            assert!(matches!(
+                check_header(&envmap(minor, 0, 6)).as_slice(),
+                [HeaderProblem::Truncated(..)]
+            ));
+        }
+    }
+
+    #[test]
+    fn envmap_faces_by_version() {
+        let faces = |data: Vec<u8>| envmap_faces(&read_header(&data).unwrap());
+        assert_eq!(faces(envmap(2, 0xFFFF, 6)), 6);
+        assert_eq!(faces(envmap(4, 0, 7)), 7);
+        assert_eq!(faces(envmap(5, 0, 6)), 6);
+    }
+}